@@ -1,58 +1,41 @@
-use std::sync::mpsc::{channel, Receiver};
-use std::thread;
+use util::mpsc::channel;
+use util::{Worker, Recovery, spawn_worker};
 use channel_traits::*;
 use user_traits::User;
+use util::{Nick, ChannelName, Hostmask, Reason};
 
 pub trait ChannelThreadFactory {
-    fn new(name: String, nick: String) -> Self;
+    fn new(name: ChannelName, nick: Nick) -> Self;
 }
 
 impl ChannelThreadFactory for ChannelThread {
-    fn new(name: String, nick: String) -> ChannelThread {
-        let (tx,rx) = channel();
-        thread::Builder::new().name("ChannelThread".to_string()).spawn(move || {
-            ChannelWorker::new(rx, name, nick).run();
-        });
+    fn new(name: ChannelName, nick: Nick) -> ChannelThread {
+        let (tx, rx) = channel();
+        spawn_worker("ChannelThread", rx, move || ChannelWorker::new(name.clone(), nick.clone()));
         tx
     }
 }
 
 pub struct ChannelWorker {
-    rx: Receiver<ChannelThreadMsg>,
-    name: String,
-    nick: String,
+    name: ChannelName,
+    #[allow(dead_code)] // whoever created the channel
+    nick: Nick,
     users: Vec<Option<User>>,
 }
 
 impl ChannelWorker {
-    fn new(rx: Receiver<ChannelThreadMsg>, name: String, nick: String) -> Self {
+    fn new(name: ChannelName, nick: Nick) -> Self {
         ChannelWorker{
-            rx: rx,
             name: name,
             nick: nick,
             users: vec![],
         }
     }
+}
+
+impl Worker for ChannelWorker {
+    type Msg = ChannelThreadMsg;
 
-    fn run(&mut self) {
-        loop {
-            lselect!{
-                msg = self.rx => {
-                    match msg {
-                        Ok(msg) => {
-                            if self.handle_msg(msg) {
-                                return;
-                            }
-                        }
-                        Err(e) => {
-                            lprintln!("DirectoryThread Got error: {:?}", e);
-                        }
-                    }
-                },
-            };
-        }
-    }
-    
     fn handle_msg(&mut self, msg: ChannelThreadMsg) -> bool {
         match msg {
             ChannelThreadMsg::Join(s, user) => {
@@ -68,16 +51,17 @@ impl ChannelWorker {
                 while self.users.len() <= i {
                     self.users.push(None);
                 }
-                s.send(i); // must come before introduce/welcome otherwise may cause deadlock
+                // the slot is filled before the user gets its id, so the id never points at an empty slot
+                self.users[i] = Some(user.clone());
+                let _ = s.send(i); // must come before introduce/welcome otherwise may cause deadlock
                 /*
                 lprintln!("=B=======================");
                 lprintln!("GOT JOIN FROM: {:?}", user.get_mask());
                 lprintln!("GOT JOIN TO: {:?}", self.name);
                 lprintln!("=========================");
                 */
-                self.introduce(&user);
+                self.introduce(i, &user);
                 self.welcome(&user);
-                self.users[i] = Some(user);
             },
             ChannelThreadMsg::Part(id, mask, reason) => {
                 /*
@@ -86,17 +70,17 @@ impl ChannelWorker {
                 lprintln!("GOT PART TO: {:?}", self.name);
                 lprintln!("=========================");
                 */
-                let reason = reason.unwrap_or("No reason provided".into());
+                let reason = reason.unwrap_or(Reason::from("No reason provided"));
                 let found = match self.users.get(id) {
                     Some(&Some(ref user)) => {
-                        user.inform_self_part(self.name.clone(), reason.clone());
+                        let _ = user.inform_self_part(self.name.clone(), reason.clone());
                         true
                     }
                     _ => false
                 };
                 if found {
-                    match self.users[id].take() {
-                        Some(user) => {
+                    match (self.users[id].take(), mask) {
+                        (Some(user), Some(mask)) => {
                             self.adios(mask, &user, reason);
                         },
                         _ => {},
@@ -104,9 +88,7 @@ impl ChannelWorker {
                 };
             },
             ChannelThreadMsg::Who(id) => {
-                let users = self.users.clone().into_iter().enumerate().filter_map(|(tid, user)| {
-                    user
-                });
+                let users = self.users.clone().into_iter().flatten();
                 match self.users.get(id) {
                     Some(&Some(ref user)) => {
                         let mut names = vec![];
@@ -115,7 +97,7 @@ impl ChannelWorker {
                                 names.push(mask.nick.to_owned())
                             }
                         }
-                        user.transmit_names(self.name.clone(), names);
+                        let _ = user.transmit_names(self.name.clone(), names);
                     },
                     _ => {}
                 }
@@ -128,17 +110,17 @@ impl ChannelWorker {
                     }
                     match user {
                         &Some(ref user) => {
-                            user.privmsg_chan(mask.clone(), self.name.clone(), msg.clone());
+                            let _ = user.privmsg_chan(mask.clone(), self.name.clone(), msg.clone());
                         },
                         _ => {}
                     }
                 }
             },
             ChannelThreadMsg::GetUsers(s) => {
-                s.send(self.users.clone().into_iter().filter_map(|user| user).collect());
+                let _ = s.send(self.users.clone().into_iter().flatten().collect());
             },
             ChannelThreadMsg::GetName(s) => {
-                s.send(self.name.clone());
+                let _ = s.send(self.name.clone());
             },
             ChannelThreadMsg::Exit => {
                 return true;
@@ -147,21 +129,36 @@ impl ChannelWorker {
         return false;
     }
 
-    fn introduce(&mut self, user: &User) {
-        let mask = user.get_mask().unwrap().for_privmsg();
-        for tuser in self.users.iter() {
+    // The members' ids live with the users, so a fresh worker couldn't tell who holds which slot,
+    // and a panic may have left the list half updated. Neither can be trusted to carry on.
+    fn recovery(&self) -> Recovery {
+        Recovery::Shutdown
+    }
+}
+
+impl ChannelWorker {
+    // tells the other members, a user that doesn't give its mask joins without them hearing of it
+    fn introduce(&mut self, id: ChannelId, user: &User) {
+        let mask = match user.get_mask() {
+            Ok(mask) => mask.for_privmsg(),
+            Err(e) => {
+                lprintln!("Couldn't get the mask of a user joining {}: {:?}", self.name, e);
+                return;
+            },
+        };
+        for (tid, tuser) in self.users.iter().enumerate() {
             match tuser {
-                &Some(ref tuser) => {tuser.inform_other_join(mask.clone(), self.name.clone());},
+                &Some(ref tuser) if tid != id => {let _ = tuser.inform_other_join(mask.clone(), self.name.clone());},
                 _ => {},
             }
         }
     }
 
-    fn adios(&mut self, mask: String, user: &User, reason: String) {
+    fn adios(&mut self, mask: Hostmask, _user: &User, reason: Reason) {
         for tuser in self.users.iter() {
             match tuser {
                 &Some(ref tuser) => {
-                    tuser.inform_other_part(mask.clone(), self.name.clone(), reason.clone());
+                    let _ = tuser.inform_other_part(mask.clone(), self.name.clone(), reason.clone());
                 },
                 _ => {},
             }
@@ -169,7 +166,7 @@ impl ChannelWorker {
     }
 
     fn welcome(&mut self, user: &User) {
-        user.inform_self_join(self.name.clone());
+        let _ = user.inform_self_join(self.name.clone());
     }
 
 }
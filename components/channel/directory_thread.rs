@@ -1,11 +1,14 @@
-use std::sync::mpsc::{channel, Receiver};
-use std::thread;
+use util::mpsc::channel;
+use util::{Worker, Recovery, spawn_worker};
 use std::collections::HashMap;
 use channel_traits::*;
-use user_traits::User;
-use std::rc::{Rc,Weak};
+use user_traits::{User, Ban, BanKind};
+use server_traits::ServerLink;
+use std::rc::Rc;
 use std::cell::RefCell;
+use std::time::Instant;
 use super::ChannelThreadFactory;
+use util::{Nick, ChannelName};
 
 pub trait DirectoryThreadFactory {
     fn new() -> Self;
@@ -13,10 +16,8 @@ pub trait DirectoryThreadFactory {
 
 impl DirectoryThreadFactory for DirectoryThread {
     fn new() -> DirectoryThread {
-        let (tx,rx) = channel();
-        thread::Builder::new().name("DirectoryThread".to_string()).spawn(move || {
-            DirectoryWorker::new(rx).run();
-        });
+        let (tx, rx) = channel();
+        spawn_worker("DirectoryThread", rx, DirectoryWorker::new);
         tx
     }
 }
@@ -24,7 +25,10 @@ impl DirectoryThreadFactory for DirectoryThread {
 #[derive(Debug)]
 struct DUserEntry {
     thread: User,
-    nick: String,
+    nick: Option<Nick>,
+    modes: Vec<char>,
+    snomask: Vec<char>,
+    local: bool,
 }
 
 #[derive(Debug)]
@@ -33,48 +37,43 @@ struct DChannelEntry {
 }
 
 pub struct DirectoryWorker {
-    rx: Receiver<DirectoryThreadMsg>,
     users: Vec<Option<Rc<RefCell<DUserEntry>>>>,
     // todo, replace Rc<_> with Weak<_>, this could lead to potential memleaks otherwise
     // The DestroyUser handler should be very carefully modified as a consequence of this decision
-    users_by_nick: HashMap<String, Rc<RefCell<DUserEntry>>>,
-    channels_by_name: HashMap<String, DChannelEntry>,
+    users_by_nick: HashMap<Nick, Rc<RefCell<DUserEntry>>>,
+    channels_by_name: HashMap<ChannelName, DChannelEntry>,
+    servers: Vec<Option<ServerLink>>,
+    local_max: usize,
+    global_max: usize,
+    started: Instant,
+    command_counts: HashMap<String, u64>,
+    bans: Vec<Ban>,
 }
 
 impl DirectoryWorker {
-    fn new(rx: Receiver<DirectoryThreadMsg>) -> Self {
+    fn new() -> Self {
         DirectoryWorker{
-            rx: rx,
             users: vec![],
             users_by_nick: HashMap::new(),
             channels_by_name: HashMap::new(),
+            servers: vec![],
+            local_max: 0,
+            global_max: 0,
+            started: Instant::now(),
+            command_counts: HashMap::new(),
+            bans: vec![],
         }
     }
+}
 
-    fn run(&mut self) {
-        loop {
-            lselect!{
-                msg = self.rx => {
-                    match msg {
-                        Ok(msg) => {
-                            if self.handle_msg(msg) {
-                                return;
-                            }
-                        }
-                        Err(e) => {
-                            lprintln!("DirectoryThread Got error: {:?}", e);
-                        }
-                    }
-                },
-            };
-        }
-    }
+impl Worker for DirectoryWorker {
+    type Msg = DirectoryThreadMsg;
 
     fn handle_msg(&mut self, msg: DirectoryThreadMsg) -> bool{
         lprintln!("Directory Thread got msg: {:?}", msg);
         match msg {
             DirectoryThreadMsg::GetChannels(s) => {
-                s.send(
+                let _ = s.send(
                     self.channels_by_name.values().map(|stored_chan| {
                         stored_chan.thread.clone()
                     }).collect()
@@ -82,13 +81,13 @@ impl DirectoryWorker {
             },
             DirectoryThreadMsg::GetChannelByName(s, name, nick) => {
                 let has_new = match self.channels_by_name.get(&name) {
-                    Some(ref channel) => {
-                        s.send(channel.thread.clone());
+                    Some(channel) => {
+                        let _ = s.send(channel.thread.clone());
                         None
                     },
                     None => {
                         let channel = Channel::new(ChannelThreadFactory::new(name.clone(), nick));
-                        s.send(channel.clone());
+                        let _ = s.send(channel.clone());
                         Some(channel)
                     }
                 };
@@ -97,15 +96,12 @@ impl DirectoryWorker {
                 }
             },
             DirectoryThreadMsg::GetUsers(s) => {
-                s.send(self.users.clone().into_iter().filter_map(|user|
-                    match user {
-                        Some(user) => Some(user.borrow().thread.clone()),
-                        None => None,
-                    }
+                let _ = s.send(self.users.clone().into_iter().filter_map(|user|
+                    user.map(|user| user.borrow().thread.clone())
                 ).collect());
             },
             DirectoryThreadMsg::GetUserByNick(s, nick) => {
-                s.send(
+                let _ = s.send(
                     match self.users_by_nick.get(&nick) {
                         Some(user) => {
                             Ok(user.borrow().thread.clone())
@@ -114,10 +110,16 @@ impl DirectoryWorker {
                     }
                 );
             },
-            DirectoryThreadMsg::NewUser(s, user) => {
+            DirectoryThreadMsg::GetLusers(s) => {
+                let _ = s.send(self.lusers());
+            },
+            DirectoryThreadMsg::NewUser(s, user, local) => {
                 let entry = DUserEntry{
                     thread: user,
-                    nick: "".into(),
+                    nick: None,
+                    modes: vec![],
+                    snomask: vec![],
+                    local: local,
                 };
                 let mut i: u64 = 0;
                 for (j, v) in self.users.iter().enumerate() {
@@ -132,13 +134,13 @@ impl DirectoryWorker {
                     self.users.push(None);
                 }
                 self.users[i as usize] = Some(Rc::new(RefCell::new(entry)));
-                s.send(i);
+                let _ = s.send(i);
             },
             DirectoryThreadMsg::DestroyUser(id) => {
                 let mut nick = None;
                 match self.users.get(id as usize) {
                     Some(&Some(ref user)) => {
-                        nick = Some(user.borrow().nick.clone());
+                        nick = user.borrow().nick.clone();
                     }
                     _ => {}
                 }
@@ -157,6 +159,8 @@ impl DirectoryWorker {
                 lprintln!("Updating nick: {:?} |||||| {:?} |||||| {:?}", nick, self.users, self.users_by_nick);
                 let nick_in_use = {
                     match self.users_by_nick.get(&nick) {
+                        // nicks differing only in case are the same nick, which a user may change its own to
+                        Some(user) if self.users.get(id as usize).and_then(|u| u.as_ref()).map(|u| Rc::ptr_eq(u, user)).unwrap_or(false) => false,
                         Some(user) => {
                             lprintln!("UpdateNick Got user: {:?}", user);
                             true
@@ -177,26 +181,240 @@ impl DirectoryWorker {
                 };
                 if nick_in_use {
                     lprintln!("Nick in use");
-                    s.send(Err(Error::NickCollision));
+                    let _ = s.send(Err(Error::NickCollision));
                     return false;
                 }
+                let mut old_nick = None;
                 match self.users.get_mut(id as usize) {
                     Some(&mut Some(ref user)) => {
                         {
                             let mut tuser = user.borrow_mut();
-                            tuser.nick = nick.clone().into();
+                            old_nick = tuser.nick.take();
+                            tuser.nick = Some(nick.clone());
+                        }
+                        // the old nick goes first, it may be equal to the new one
+                        if let Some(ref old_nick) = old_nick {
+                            self.users_by_nick.remove(old_nick);
                         }
                         self.users_by_nick.insert(nick.clone(), user.clone());//Rc::downgrade(user));
                         //lprintln!("ATTEMT IMMEDIATE UPGRADE: {:?}", self.users_by_nick.get(&nick).unwrap().upgrade());
                     }
                     _ => {}
                 }
-                s.send(Ok(()));
+                let _ = s.send(Ok(()));
+                let lusers = self.lusers();
+                self.local_max = ::std::cmp::max(self.local_max, lusers.local_users);
+                self.global_max = ::std::cmp::max(self.global_max, lusers.local_users + lusers.virtual_users);
+                if let Some(old_nick) = old_nick {
+                    self.server_notice('n', format!("Nick change: From {} to {}", old_nick, nick));
+                }
+            },
+            DirectoryThreadMsg::UpdateModes(id, modes, snomask) => {
+                match self.users.get(id as usize) {
+                    Some(&Some(ref user)) => {
+                        let mut tuser = user.borrow_mut();
+                        tuser.modes = modes;
+                        tuser.snomask = snomask;
+                    }
+                    _ => {}
+                }
+            },
+            DirectoryThreadMsg::ServerNotice(snomask, msg) => {
+                self.server_notice(snomask, msg);
+            },
+            DirectoryThreadMsg::Wallops(origin, src, msg) => {
+                for user in self.users.iter() {
+                    match user {
+                        &Some(ref user) => {
+                            let user = user.borrow();
+                            if user.modes.contains(&'w') {
+                                let _ = user.thread.wallops(src.clone(), msg.clone());
+                            }
+                        },
+                        _ => {},
+                    }
+                }
+                for (id, server) in self.servers.iter().enumerate() {
+                    match server {
+                        &Some(ref server) if origin != Some(id as DirectoryId) => {
+                            let _ = server.wallops(src.clone(), msg.clone());
+                        },
+                        _ => {},
+                    }
+                }
+            },
+            DirectoryThreadMsg::Globops(origin, src, msg) => {
+                for user in self.users.iter() {
+                    match user {
+                        &Some(ref user) => {
+                            let user = user.borrow();
+                            if user.modes.contains(&'o') {
+                                let _ = user.thread.globops(src.clone(), msg.clone());
+                            }
+                        },
+                        _ => {},
+                    }
+                }
+                for (id, server) in self.servers.iter().enumerate() {
+                    match server {
+                        &Some(ref server) if origin != Some(id as DirectoryId) => {
+                            let _ = server.globops(src.clone(), msg.clone());
+                        },
+                        _ => {},
+                    }
+                }
+            },
+            DirectoryThreadMsg::NewServer(s, link) => {
+                let i = match self.servers.iter().position(|server| server.is_none()) {
+                    Some(i) => i,
+                    None => {
+                        self.servers.push(None);
+                        self.servers.len() - 1
+                    }
+                };
+                self.servers[i] = Some(link);
+                let _ = s.send(i as DirectoryId);
+            },
+            DirectoryThreadMsg::DestroyServer(id) => {
+                if let Some(server) = self.servers.get_mut(id as usize) {
+                    *server = None;
+                }
             },
-            DirectoryThreadMsg::Exit => {
+            DirectoryThreadMsg::GetServers(s) => {
+                let _ = s.send(self.servers.iter().filter_map(|server| server.clone()).collect());
+            },
+            DirectoryThreadMsg::GetUptime(s) => {
+                let _ = s.send(self.started.elapsed().as_secs());
+            },
+            DirectoryThreadMsg::RecordCommands(commands) => {
+                for (command, count) in commands.into_iter() {
+                    *self.command_counts.entry(command).or_insert(0) += count;
+                }
+            },
+            DirectoryThreadMsg::GetCommandStats(s) => {
+                let _ = s.send(self.command_counts.clone());
+            },
+            DirectoryThreadMsg::AddBan(origin, ban) => {
+                self.bans.retain(|b| !(b.kind == ban.kind && b.mask() == ban.mask()));
+                self.bans.push(ban.clone());
+                self.server_notice('k', format!("{} added {} for {} ({})", ban.set_by, ban.kind.name(), ban.mask(), ban.reason));
+                if ban.kind == BanKind::GLine {
+                    for (id, server) in self.servers.iter().enumerate() {
+                        match server {
+                            &Some(ref server) if origin != Some(id as DirectoryId) => {
+                                let _ = server.add_ban(ban.clone());
+                            },
+                            _ => {},
+                        }
+                    }
+                }
+                for user in self.users.iter() {
+                    match user {
+                        &Some(ref user) if user.borrow().local => {
+                            let _ = user.borrow().thread.check_ban(ban.clone());
+                        },
+                        _ => {},
+                    }
+                }
+            },
+            DirectoryThreadMsg::RemoveBan(s, origin, kind, mask, removed_by) => {
+                let before = self.bans.len();
+                self.bans.retain(|b| !(b.kind == kind && b.mask() == mask));
+                let removed = self.bans.len() != before;
+                let _ = s.send(removed);
+                if removed {
+                    self.server_notice('k', format!("{} removed {} for {}", removed_by, kind.name(), mask));
+                    if kind == BanKind::GLine {
+                        for (id, server) in self.servers.iter().enumerate() {
+                            match server {
+                                &Some(ref server) if origin != Some(id as DirectoryId) => {
+                                    let _ = server.remove_ban(kind, mask.clone(), removed_by.clone());
+                                },
+                                _ => {},
+                            }
+                        }
+                    }
+                }
+            },
+            DirectoryThreadMsg::FindBan(s, kinds, user, host, ip) => {
+                self.bans.retain(|b| !b.is_expired());
+                let _ = s.send(self.bans.iter().find(|b| kinds.contains(&b.kind) && b.matches(&user, &host, &ip)).cloned());
+            },
+            DirectoryThreadMsg::GetBans(s, kind) => {
+                self.bans.retain(|b| !b.is_expired());
+                let _ = s.send(self.bans.iter().filter(|b| b.kind == kind).cloned().collect());
+            },
+            DirectoryThreadMsg::Shutdown(reason) => {
+                // links first, so the other servers hear one SQUIT rather than a QUIT per user
+                for server in self.servers.iter().flatten() {
+                    let _ = server.exit(reason.clone());
+                }
+                for user in self.users.iter().flatten() {
+                    let user = user.borrow();
+                    if user.local {
+                        let _ = user.thread.exit(reason.clone());
+                    }
+                }
+            },
+            DirectoryThreadMsg::Exit(s) => {
+                for channel in self.channels_by_name.values() {
+                    let _ = channel.thread.exit();
+                }
+                let _ = s.send(());
                 return true;
             },
         }
         return false;
     }
+
+    // the ids it handed out to users, channels and servers can't be made again
+    fn recovery(&self) -> Recovery {
+        Recovery::Shutdown
+    }
+}
+
+impl DirectoryWorker {
+    fn lusers(&self) -> Lusers {
+        let mut lusers = Lusers{
+            channels: self.channels_by_name.len(),
+            servers: self.servers.iter().filter(|server| server.is_some()).count(),
+            local_max: self.local_max,
+            global_max: self.global_max,
+            ..Default::default()
+        };
+        for user in self.users.iter() {
+            if let &Some(ref user) = user {
+                let user = user.borrow();
+                if !user.local {
+                    lusers.virtual_users += 1;
+                } else if user.nick.is_none() {
+                    lusers.unknown += 1;
+                } else {
+                    lusers.local_users += 1;
+                    if user.modes.contains(&'i') {
+                        lusers.invisible += 1;
+                    }
+                    if user.modes.contains(&'o') {
+                        lusers.opers += 1;
+                    }
+                }
+            }
+        }
+        lusers
+    }
+
+    fn server_notice(&self, snomask: char, msg: String) {
+        lprintln!("Server notice [{}]: {}", snomask, msg);
+        for user in self.users.iter() {
+            match user {
+                &Some(ref user) => {
+                    let user = user.borrow();
+                    if user.modes.contains(&'o') && user.snomask.contains(&snomask) {
+                        let _ = user.thread.server_notice(msg.clone());
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
 }
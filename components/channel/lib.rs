@@ -1,8 +1,8 @@
-#![feature(mpsc_select)]
 #[macro_use]
 extern crate util;
 extern crate channel_traits;
 extern crate user_traits;
+extern crate server_traits;
 
 pub mod directory_thread;
 pub mod channel_thread;
@@ -1,24 +1,22 @@
-use std::sync::mpsc::{channel, Sender};
 use std::sync::Arc;
-use super::Result;
+use super::{Result, Error};
 use user_traits::User;
 use std::sync::RwLock;
-
-pub type ChannelThread = Sender<ChannelThreadMsg>;
+use util::{ChannelName, Hostmask, Reason, MessageText};
 
 pub type ChannelId = usize;
 
-#[derive(Debug)]
-pub enum ChannelThreadMsg {
+actor!{
+    pub struct Channel(ChannelThread, ChannelThreadMsg) -> Error, timeout_ms 5000;
     // INVARIANT: The Sender of this Join msg MUST place the ChannelId into a new ChannelEntry to ensure proper cleanup BEFORE any cloning to prevent double-free
     // it is impossible to handle this within the ChannelThread itself because it would create a circular reference. Even though it would work fine, it would prevent the DirectoryThread from automatically cleaning up
-    Join(Sender<ChannelId>, User),
-    Part(ChannelId, String, Option<String>),
-    Privmsg(ChannelId, String, String),
-    Who(ChannelId),
-    GetUsers(Sender<Vec<User>>),
-    GetName(Sender<String>),
-    Exit,
+    req fn join_id(user: User) -> ChannelId => Join;
+    send fn part(id: ChannelId, mask: Option<Hostmask>, reason: Option<Reason>) => Part;
+    send fn privmsg(id: ChannelId, mask: Hostmask, msg: MessageText) => Privmsg;
+    send fn who(id: ChannelId) => Who;
+    pub req fn get_users() -> Vec<User> => GetUsers;
+    pub req fn get_name() -> ChannelName => GetName;
+    pub send fn exit() => Exit;
 }
 
 #[derive(Debug, Clone)]
@@ -34,77 +32,57 @@ impl ChannelEntry {
             arc: Arc::new(RwLock::new(StoredChannelId{
                 channel: channel,
                 part_reason: None,
-                mask: "".into(),
+                mask: None,
                 id: id,
             })),
         }
     }
 
-    pub fn update_mask(&self, mask: String) {
+    // the mask the channel is told the user parted with
+    pub fn update_mask(&self, mask: Hostmask) {
         let mut locked = self.arc.write().unwrap();
-        locked.mask = mask.into();
+        locked.mask = Some(mask);
     }
 
-    pub fn part_reason(&self, reason: Option<String>) {
+    pub fn part_reason(&self, reason: Option<Reason>) {
         let mut locked = self.arc.write().unwrap();
         locked.part_reason = reason;
     }
 
-    pub fn privmsg(&self, mask: String, msg: String) -> Result<()>{
+    pub fn privmsg(&self, mask: Hostmask, msg: MessageText) -> Result<()>{
         let locked = self.arc.read().unwrap();
-        try!(send!(locked.channel.thread, ChannelThreadMsg::Privmsg => (locked.id, mask, msg)));
-        Ok(())
+        locked.channel.privmsg(locked.id, mask, msg)
     }
 
     pub fn who(&self) -> Result<()> {
         let locked = self.arc.read().unwrap();
-        try!(send!(locked.channel.thread, ChannelThreadMsg::Who => (locked.id)));
-        Ok(())
+        locked.channel.who(locked.id)
     }
 }
 
 #[derive(Debug)]
 struct StoredChannelId {
     channel: Channel,
-    part_reason: Option<String>,
-    mask: String,
+    part_reason: Option<Reason>,
+    mask: Option<Hostmask>,
     id: ChannelId,
 }
 
 impl Drop for StoredChannelId {
     fn drop(&mut self) {
         lprintln!("Dropping Channel ID -- {:?} -- {:?}", self.id, self.part_reason);
-        self.channel.part(self.id, self.mask.clone(), self.part_reason.take()).unwrap();
+        // a channel thread that has gone has no one left to tell
+        if let Err(e) = self.channel.part(self.id, self.mask.take(), self.part_reason.take()) {
+            lprintln!("Couldn't part Channel ID -- {:?} -- {:?}", self.id, e);
+        }
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct Channel {
-    thread: ChannelThread,
-}
-
 impl Channel {
-    pub fn new(thread: ChannelThread) -> Self {
-        Channel{ thread: thread }
-    }
-
     pub fn join(&self, user: User) -> Result<ChannelEntry> {
         unsafe{
-            let id = try!(req_rep!(self.thread, ChannelThreadMsg::Join => (user)));
+            let id = self.join_id(user)?;
             Ok(ChannelEntry::new(self.clone(), id))
         }
     }
-
-    fn part(&self, id: ChannelId, mask: String, reason: Option<String>) -> Result<()> {
-        try!(send!(self.thread, ChannelThreadMsg::Part => (id, mask, reason)));
-        Ok(())
-    }
-
-    pub fn get_name(&self) -> Result<String> {
-        Ok(try!(req_rep!(self.thread, ChannelThreadMsg::GetName => ())))
-    }
-
-    pub fn get_users(&self) -> Result<Vec<User>> {
-        Ok(try!(req_rep!(self.thread, ChannelThreadMsg::GetUsers => ())))
-    }
 }
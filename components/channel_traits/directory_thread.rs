@@ -1,25 +1,59 @@
-use std::sync::mpsc::{channel, Sender};
 use std::sync::Arc;
-use super::Result;
+use std::collections::HashMap;
+use super::{Result, Error};
 use super::Channel;
-use user_traits::User;
-
-pub type DirectoryThread = Sender<DirectoryThreadMsg>;
+use user_traits::{User, Ban, BanKind};
+use server_traits::ServerLink;
+use util::{Nick, ChannelName, Hostmask, Reason, MessageText};
 
 pub type DirectoryId = u64;
 
-#[derive(Debug)]
-pub enum DirectoryThreadMsg {
-    GetChannels(Sender<Vec<Channel>>),
-    GetChannelByName(Sender<Channel>, String, String),
-    GetUsers(Sender<Vec<User>>),
-    GetUserByNick(Sender<Result<User>>, String),
+#[derive(Debug, Clone, Default)]
+pub struct Lusers {
+    pub local_users: usize,
+    pub virtual_users: usize,
+    pub invisible: usize,
+    pub opers: usize,
+    pub unknown: usize,
+    pub channels: usize,
+    pub servers: usize,
+    pub local_max: usize,
+    pub global_max: usize,
+}
+
+actor!{
+    pub struct Directory(DirectoryThread, DirectoryThreadMsg) -> Error, timeout_ms 5000;
+    pub req fn get_channels() -> Vec<Channel> => GetChannels;
+    pub req fn get_channel_by_name(name: ChannelName, nick: Nick) -> Channel => GetChannelByName;
+    pub req fn get_users() -> Vec<User> => GetUsers;
+    pub try_req fn get_user_by_nick(nick: Nick) -> User => GetUserByNick;
+    pub req fn get_lusers() -> Lusers => GetLusers;
     // INVARIANT: The Sender of this NewUser msg MUST place this Id into a new DirectoryEntry to ensure proper cleanup BEFORE any cloning to prevent double-free
     // it is impossible to handle this within the DirectoryThread itself because it would create a circular reference. even though it would work fine, it would  prevent the DirectoryThread from automatically cleaning up
-    NewUser(Sender<DirectoryId>, User),
-    UpdateNick(Sender<Result<()>>,DirectoryId, String),
-    DestroyUser(DirectoryId),
-    Exit,
+    req fn new_user_id(user: User, local: bool) -> DirectoryId => NewUser;
+    try_req fn update_nick(id: DirectoryId, nick: Nick) -> () => UpdateNick;
+    send fn update_modes(id: DirectoryId, modes: Vec<char>, snomask: Vec<char>) => UpdateModes;
+    pub send fn server_notice(snomask: char, msg: String) => ServerNotice;
+    // origin is the server link the message arrived on, it will not be relayed back to it
+    pub send fn wallops(origin: Option<DirectoryId>, src: Hostmask, msg: MessageText) => Wallops;
+    pub send fn globops(origin: Option<DirectoryId>, src: Nick, msg: MessageText) => Globops;
+    pub req fn new_server(link: ServerLink) -> DirectoryId => NewServer;
+    pub req fn get_servers() -> Vec<ServerLink> => GetServers;
+    pub req fn get_uptime() -> u64 => GetUptime; // seconds
+    // command usage of a connection that has ended, so that STATS m survives disconnects
+    pub send fn record_commands(commands: HashMap<String, u64>) => RecordCommands;
+    pub req fn get_command_stats() -> HashMap<String, u64> => GetCommandStats;
+    // adding a ban disconnects every matching user, G-Lines are also propagated to the other servers
+    pub send fn add_ban(origin: Option<DirectoryId>, ban: Ban) => AddBan;
+    pub req fn remove_ban(origin: Option<DirectoryId>, kind: BanKind, mask: String, removed_by: String) -> bool => RemoveBan;
+    pub req fn find_ban(kinds: Vec<BanKind>, user: String, host: String, ip: String) -> Option<Ban> => FindBan;
+    pub req fn get_bans(kind: BanKind) -> Vec<Ban> => GetBans;
+    pub send fn destroy_server(id: DirectoryId) => DestroyServer;
+    pub send fn destroy_user(id: DirectoryId) => DestroyUser;
+    // every server link and local user is told to exit, the connections end on their own after that
+    pub send fn shutdown(reason: Reason) => Shutdown;
+    // once they have, the channels exit and so does the directory
+    pub req fn exit() -> () => Exit;
 }
 
 #[derive(Debug,Clone)]
@@ -47,10 +81,11 @@ impl DirectoryEntry {
             })
         }
     }
-    pub fn update_nick(&self, nick: String) -> Result<()> {
-        let stored = self.id.clone();
-        try!(try!(req_rep!(stored.directory.thread, DirectoryThreadMsg::UpdateNick => (stored.id, nick))));
-        Ok(())
+    pub fn update_nick(&self, nick: Nick) -> Result<()> {
+        self.id.directory.update_nick(self.id.id, nick)
+    }
+    pub fn update_modes(&self, modes: Vec<char>, snomask: Vec<char>) -> Result<()> {
+        self.id.directory.update_modes(self.id.id, modes, snomask)
     }
 }
 
@@ -58,48 +93,18 @@ impl Drop for StoredDirectoryId {
     fn drop(&mut self) {
         lprintln!("Dropping Directory ID -- {:?}", self.id);
         // we're about to wipe out the last reference, inform directory thread of its destruction
-        self.directory.destroy_user(self.id).unwrap();
+        if let Err(e) = self.directory.destroy_user(self.id) {
+            lprintln!("Couldn't destroy Directory ID -- {:?} -- {:?}", self.id, e);
+        }
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct Directory {
-    thread: DirectoryThread,
-}
-
 impl Directory {
-    pub fn new(thread: DirectoryThread) -> Self {
-        Directory{ thread: thread }
-    }
-
-    pub fn new_user(&self, user: User) -> Result<DirectoryEntry> {
+    pub fn new_user(&self, user: User, local: bool) -> Result<DirectoryEntry> {
         unsafe{
-            let id = try!(req_rep!(self.thread, DirectoryThreadMsg::NewUser => (user)));
+            let id = self.new_user_id(user, local)?;
             lprintln!("User got id: {:?}", id);
             Ok(DirectoryEntry::new(self.clone(), id))
         }
     }
-
-    pub fn destroy_user(&self, id: DirectoryId) -> Result<()> {
-        try!(send!(self.thread, DirectoryThreadMsg::DestroyUser => (id)));
-        Ok(())
-    }
-
-    pub fn get_users(&self) -> Result<Vec<User>> {
-        Ok(try!(req_rep!(self.thread, DirectoryThreadMsg::GetUsers => ())))
-    }
-
-    pub fn get_user_by_nick(&self, nick: String) -> Result<User> {
-        try!(req_rep!(self.thread, DirectoryThreadMsg::GetUserByNick => (nick)))
-    }
-
-    pub fn get_channels(&self) -> Result<Vec<Channel>> {
-        Ok(try!(req_rep!(self.thread, DirectoryThreadMsg::GetChannels => ())))
-    }
-
-    pub fn get_channel_by_name(&self, name: String, nick: String) -> Result<Channel> {
-        Ok(try!(req_rep!(self.thread, DirectoryThreadMsg::GetChannelByName => (name, nick))))
-    }
 }
-
-
@@ -9,6 +9,7 @@ pub enum Error {
     NickNotFound,
     SendError(&'static str),
     RecvError(&'static str),
+    Timeout(&'static str),
 }
 
 impl From<ChanError> for Error {
@@ -16,6 +17,7 @@ impl From<ChanError> for Error {
         match err {
             ChanError::SendError(err) => Error::SendError(err),
             ChanError::RecvError(err) => Error::RecvError(err),
+            ChanError::Timeout(err) => Error::Timeout(err),
         }
     }
 }
@@ -1,7 +1,7 @@
-//#![feature(mpsc_select)]
 #[macro_use]
 extern crate util;
 extern crate user_traits;
+extern crate server_traits;
 
 pub mod error;
 pub mod channel_thread;
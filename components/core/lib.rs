@@ -1,11 +1,26 @@
+#[macro_use]
+extern crate util;
 extern crate net;
 extern crate channel;
 extern crate channel_traits;
 extern crate server;
 extern crate server_traits;
+extern crate user;
+extern crate signal_hook;
 
 use std::path::Path;
 use std::env;
+use std::process::{self, Command};
+use std::os::unix::process::CommandExt;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use util::{Stop, Reason};
+
+// how long clients and links get to be told and have it go out before the process ends anyway
+const SHUTDOWN_SECS: u64 = 10;
 
 pub fn run() {
     let arg = env::args().nth(1);
@@ -13,7 +28,55 @@ pub fn run() {
         println!("Usage: server.bin [config.yaml]");
         return;
     }
-    let config = server_traits::Config::new(server::ConfigThreadFactory::new(server::parse_config(Path::new(&arg.unwrap()))));
+    let arg = arg.unwrap();
+    let path = Path::new(&arg);
+    // signals, DIE, RESTART and workers that can't carry on all stop the server through here
+    let (tx, rx) = util::mpsc::channel();
+    util::set_stop_handler(move |reason, how| { let _ = tx.send((reason, how)); });
+    watch_signals();
+    let config = server_traits::Config::new(server::ConfigThreadFactory::new(path.to_path_buf(), server::parse_config(path)));
     let directory = channel_traits::Directory::new(channel::DirectoryThreadFactory::new());
-    net::run(directory, config);
+    let net = net::run(directory.clone(), config, Arc::new(user::SystemResolver));
+
+    let (reason, how): (String, Stop) = rx.recv().unwrap();
+    let _ = directory.server_notice('s', format!("Server stopping: {}", reason));
+    let deadline = Instant::now() + Duration::from_secs(SHUTDOWN_SECS);
+    let closing = match how {
+        Stop::Restart => "Server restarting",
+        _ => "Server shutting down",
+    };
+    if !net.shutdown(&directory, Reason::from(closing), deadline) {
+        lprintln!("Not everything had stopped after {} seconds, stopping anyway", SHUTDOWN_SECS);
+    }
+    if let Err(e) = directory.exit() {
+        lprintln!("Directory didn't exit: {:?}", e);
+    }
+    match how {
+        Stop::Die => process::exit(0),
+        Stop::Restart => restart(),
+        Stop::Fatal => process::exit(1),
+    }
+}
+
+// the first SIGTERM or SIGINT shuts down cleanly, another one while that's going on exits straight away
+fn watch_signals() {
+    let mut signals = Signals::new([SIGTERM, SIGINT]).unwrap();
+    thread::Builder::new().name("SignalThread".to_string()).spawn(move || {
+        for (count, signal) in signals.forever().enumerate() {
+            if count > 0 {
+                lprintln!("Got signal {} while stopping, exiting now", signal);
+                process::exit(1);
+            }
+            util::stop(format!("Got signal {}", signal), Stop::Die);
+        }
+    }).unwrap();
+}
+
+// runs the binary again in place of this process, so a rebuilt one is picked up
+fn restart() {
+    let mut args = env::args_os();
+    let program = args.next().unwrap();
+    let err = Command::new(program).args(args).exec();
+    lprintln!("Couldn't restart: {:?}", err);
+    process::exit(1);
 }
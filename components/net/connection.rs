@@ -0,0 +1,413 @@
+use std::io;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use util::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use mio::{Interest, Registry, Token};
+use mio::event::Source;
+use mio::net::{TcpStream, UnixStream};
+use rustls::ServerConnection;
+
+use net_traits::*;
+use linefsm::LineFSM;
+use stream::{Socket, tls_error};
+use websocket::WebSocket;
+use util::Reason;
+
+// longest line accepted before the connection is dropped, the same as the longest WebSocket message
+const MAX_LINE: usize = 16384;
+// how long a connection whose user has gone may take to receive what was still queued for it
+const LINGER_SECS: u64 = 10;
+
+// a socket in non-blocking mode, as registered with an event loop
+pub enum MioSocket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl MioSocket {
+    pub fn new(socket: Socket) -> io::Result<Self> {
+        Ok(match socket {
+            Socket::Tcp(s) => {
+                s.set_nonblocking(true)?;
+                MioSocket::Tcp(TcpStream::from_std(s))
+            },
+            Socket::Unix(s) => {
+                s.set_nonblocking(true)?;
+                MioSocket::Unix(UnixStream::from_std(s))
+            },
+        })
+    }
+}
+
+impl Read for MioSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            &mut MioSocket::Tcp(ref mut s) => s.read(buf),
+            &mut MioSocket::Unix(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for MioSocket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            &mut MioSocket::Tcp(ref mut s) => s.write(buf),
+            &mut MioSocket::Unix(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            &mut MioSocket::Tcp(ref mut s) => s.flush(),
+            &mut MioSocket::Unix(ref mut s) => s.flush(),
+        }
+    }
+}
+
+impl Source for MioSocket {
+    fn register(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        match self {
+            &mut MioSocket::Tcp(ref mut s) => s.register(registry, token, interests),
+            &mut MioSocket::Unix(ref mut s) => s.register(registry, token, interests),
+        }
+    }
+
+    fn reregister(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+        match self {
+            &mut MioSocket::Tcp(ref mut s) => s.reregister(registry, token, interests),
+            &mut MioSocket::Unix(ref mut s) => s.reregister(registry, token, interests),
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            &mut MioSocket::Tcp(ref mut s) => s.deregister(registry),
+            &mut MioSocket::Unix(ref mut s) => s.deregister(registry),
+        }
+    }
+}
+
+// what an event loop needs to drain the Writer handed to a connection's user
+pub struct WriterEnd {
+    pub token: Token,
+    pub rx: Receiver<WriterThreadMsg>,
+    pub woken: Arc<AtomicBool>, // Set while the connection is waiting to be drained
+    pub closed: Arc<Mutex<Option<String>>>,
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    Open,
+    Draining(Instant), // The user has gone, writing out what is left until the deadline
+    Done,
+}
+
+// A client connection as the event loop sees it. Reads are decoded (TLS, WebSocket, lines) and
+// handed to the user's ReaderThread, and messages from its Writer are rendered and encoded into
+// the sendq, which is written out whenever the socket can take more.
+pub struct Connection {
+    pub token: Token,
+    pub info: ConnectionInfo,
+    socket: MioSocket,
+    tls: Option<ServerConnection>,
+    websocket: Option<WebSocket>,
+    fsm: LineFSM,
+    lines: Vec<u8>, // Received bytes that aren't a whole line yet
+    reader: Option<ReaderThread>,
+    reader_stats: ReaderStats,
+    rx: Receiver<WriterThreadMsg>,
+    woken: Arc<AtomicBool>,
+    closed: Arc<Mutex<Option<String>>>,
+    sendq: Vec<u8>,
+    sendq_limit: usize,
+    writable: bool, // Whether the loop is waiting for the socket to take more
+    data: WriterData,
+    stats: WriterStats,
+    state: State,
+}
+
+impl Connection {
+    pub fn new(socket: Socket, tls: Option<ServerConnection>, websocket: Option<WebSocket>, writer: WriterEnd, reader: ReaderThread, info: ConnectionInfo, server_name: String, sendq_limit: usize) -> io::Result<Self> {
+        // the name is handed over up front, the loop never waits on the config thread
+        let data = WriterData{ server_name: server_name, ..Default::default() };
+        Ok(Connection{
+            token: writer.token,
+            info: info,
+            socket: MioSocket::new(socket)?,
+            tls: tls,
+            websocket: websocket,
+            fsm: LineFSM::new(),
+            lines: vec![],
+            reader: Some(reader),
+            reader_stats: Default::default(),
+            rx: writer.rx,
+            woken: writer.woken,
+            closed: writer.closed,
+            sendq: vec![],
+            sendq_limit: sendq_limit,
+            writable: false,
+            data: data,
+            stats: Default::default(),
+            state: State::Open,
+        })
+    }
+
+    pub fn register(&mut self, registry: &Registry) -> io::Result<()> {
+        self.socket.register(registry, self.token, Interest::READABLE)?;
+        // the TLS handshake may have taken in more than it needed, and the user may have written already
+        self.receive(&[]);
+        self.drain_writer();
+        Ok(())
+    }
+
+    pub fn deregister(&mut self, registry: &Registry) {
+        let _ = self.socket.deregister(registry);
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    pub fn is_draining(&self) -> bool {
+        matches!(self.state, State::Draining(_))
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        if let State::Draining(deadline) = self.state {
+            if now >= deadline {
+                self.state = State::Done;
+            }
+        }
+    }
+
+    // closes the connection on the server's behalf, when whoever should have told the user can't
+    pub fn shut_down(&mut self, reason: &Reason) {
+        self.drain_writer();
+        if self.state == State::Open {
+            let raw = RPL::ClosingLink(reason.clone()).raw(&mut self.data);
+            lprintln!(">> {} -- shutting down", raw);
+            self.send(format!("{}\r\n", raw).into_bytes());
+            self.finish();
+        }
+    }
+
+    pub fn readable(&mut self) {
+        let mut buf = [0u8; 4096];
+        while self.state == State::Open {
+            match self.socket.read(&mut buf) {
+                Ok(0) => self.close(None),
+                Ok(n) => self.receive(&buf[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => self.close(Some(format!("Read error: {}", e))),
+            }
+        }
+    }
+
+    fn receive(&mut self, input: &[u8]) {
+        let (mut plain, open) = match self.decrypt(input) {
+            Ok(decrypted) => decrypted,
+            Err(e) => return self.close(Some(format!("Read error: {}", e))),
+        };
+        let mut closing = false; // Whether the client has sent a WebSocket close
+        if self.websocket.is_some() {
+            let (mut lines, mut replies) = (vec![], vec![]);
+            match self.websocket.as_mut().unwrap().decode(&plain, &mut lines, &mut replies) {
+                Ok(still_open) => closing = !still_open,
+                Err(e) => return self.close(Some(format!("Read error: {}", e))),
+            }
+            self.push(&replies);
+            plain = lines;
+        }
+        self.lines.extend_from_slice(&plain);
+        while let Some(end) = self.lines.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.lines.drain(..end + 1).collect();
+            self.reader_stats.bytes_in += line.len() as u64;
+            self.reader_stats.messages_in += 1;
+            // empty lines don't parse, and are skipped
+            let cmd = match self.fsm.handle_line(String::from_utf8_lossy(&line).into_owned()) {
+                Ok(cmd) => cmd,
+                Err(_) => continue,
+            };
+            let sent = match self.reader {
+                Some(ref reader) => send!(reader, ReaderThreadMsg::Command => (cmd)).is_ok(),
+                None => false,
+            };
+            if !sent {
+                return self.close(None);
+            }
+        }
+        if self.lines.len() > MAX_LINE {
+            return self.close(Some("Line too long".into()));
+        }
+        if !open {
+            return self.close(None);
+        }
+        // the close frame echoed back still has to go out, which completes the closing handshake
+        if closing {
+            self.finish();
+        }
+    }
+
+    // returns the plaintext, and false once the client has closed the TLS session
+    fn decrypt(&mut self, input: &[u8]) -> io::Result<(Vec<u8>, bool)> {
+        let tls = match self.tls {
+            Some(ref mut tls) => tls,
+            None => return Ok((input.to_vec(), true)),
+        };
+        let mut input = input;
+        let mut plain = vec![];
+        let mut buf = [0u8; 4096];
+        loop {
+            if input.len() > 0 {
+                tls.read_tls(&mut input)?;
+            }
+            if let Err(e) = tls.process_new_packets() {
+                // let the client know why before giving up
+                let _ = tls.write_tls(&mut self.sendq);
+                return Err(tls_error(e));
+            }
+            loop {
+                match tls.reader().read(&mut buf) {
+                    Ok(0) => return Ok((plain, false)),
+                    Ok(n) => plain.extend_from_slice(&buf[..n]),
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e),
+                }
+            }
+            if input.len() == 0 {
+                return Ok((plain, true));
+            }
+        }
+    }
+
+    // renders whatever the user has written since the connection was last woken
+    pub fn drain_writer(&mut self) {
+        // cleared first, so that anything written from here on wakes the connection again
+        self.woken.store(false, Ordering::SeqCst);
+        while self.state == State::Open {
+            match self.rx.try_recv() {
+                Ok(msg) => self.handle_writer_msg(msg),
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => return self.finish(),
+            }
+        }
+    }
+
+    fn handle_writer_msg(&mut self, msg: WriterThreadMsg) {
+        match msg {
+            WriterThreadMsg::SendRaw(raw) => {
+                lprintln!(">> (raw) {}", raw);
+                self.send(raw.into_bytes());
+            },
+            WriterThreadMsg::SSend(rpl) => {
+                let raw = rpl.raw(&mut self.data);
+                lprintln!(">> {} -- from {:?}", raw, rpl);
+                self.send(format!("{}\r\n", raw).into_bytes());
+            },
+            WriterThreadMsg::Send(rpl) => {
+                let raw = rpl.raw(&mut self.data);
+                lprintln!(">> {} -- from {:?}", raw, rpl);
+                self.send(format!("{}\r\n", raw).into_bytes());
+            },
+            WriterThreadMsg::UpdateNick(nick) => {
+                self.data.nick = nick.to_string()
+            },
+            WriterThreadMsg::GetStats(s) => {
+                self.stats.sendq = self.sendq.len();
+                let _ = s.send((self.reader_stats.clone(), self.stats.clone()));
+            },
+        }
+    }
+
+    fn send(&mut self, bytes: Vec<u8>) {
+        if self.sendq.len() + bytes.len() > self.sendq_limit {
+            return self.close(Some("SendQ exceeded".into()));
+        }
+        self.stats.bytes_out += bytes.len() as u64;
+        self.stats.messages_out += 1;
+        match self.websocket {
+            Some(ref websocket) => {
+                let mut frames = vec![];
+                websocket.encode(&bytes, &mut frames);
+                self.push(&frames);
+            },
+            None => self.push(&bytes),
+        }
+    }
+
+    // queues bytes that are ready to go out apart from encryption
+    fn push(&mut self, bytes: &[u8]) {
+        match self.tls {
+            Some(ref mut tls) => {
+                let _ = tls.writer().write_all(bytes);
+                while tls.wants_write() {
+                    if tls.write_tls(&mut self.sendq).is_err() {
+                        break;
+                    }
+                }
+            },
+            None => self.sendq.extend_from_slice(bytes),
+        }
+    }
+
+    // writes as much of the sendq as the socket takes, and waits for it to take more if needed
+    pub fn flush(&mut self, registry: &Registry) {
+        if self.state == State::Done {
+            return;
+        }
+        let mut written = 0;
+        while written < self.sendq.len() {
+            match self.socket.write(&self.sendq[written..]) {
+                Ok(0) => break,
+                Ok(n) => written += n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => {
+                    self.sendq.drain(..written);
+                    return self.close(Some(format!("Write error: {}", e)));
+                },
+            }
+        }
+        self.sendq.drain(..written);
+        if self.sendq.len() == 0 && self.is_draining() {
+            self.state = State::Done;
+            return;
+        }
+        let writable = self.sendq.len() > 0;
+        if writable != self.writable {
+            let interest = if writable { Interest::READABLE | Interest::WRITABLE } else { Interest::READABLE };
+            if let Err(e) = self.socket.reregister(registry, self.token, interest) {
+                return self.close(Some(format!("Write error: {}", e)));
+            }
+            self.writable = writable;
+        }
+    }
+
+    // Drops the connection straight away, with the reason its user quits with. Dropping the
+    // ReaderThread is what tells the user the connection has gone.
+    fn close(&mut self, reason: Option<String>) {
+        if let Some(reason) = reason {
+            lprintln!("Closing connection from {}: {}", self.info.ip, reason);
+            let mut closed = self.closed.lock().unwrap();
+            if closed.is_none() {
+                *closed = Some(reason);
+            }
+        }
+        self.reader = None;
+        self.state = State::Done;
+    }
+
+    // the user has gone, eg after QUIT, but what it wrote last (the ERROR line) should still arrive
+    fn finish(&mut self) {
+        self.reader = None;
+        if let Some(ref mut tls) = self.tls {
+            tls.send_close_notify();
+        }
+        self.push(&[]);
+        self.state = State::Draining(Instant::now() + Duration::from_secs(LINGER_SECS));
+    }
+}
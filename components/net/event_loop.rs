@@ -0,0 +1,374 @@
+use util::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::collections::HashMap;
+use std::thread::{self, JoinHandle};
+use std::io;
+use std::time::{Duration, Instant};
+
+use mio::{Events, Poll, Token, Waker};
+use net_traits::{Writer, WriterWaker, Throttle};
+use connection::{Connection, WriterEnd};
+use util::Reason;
+
+// how many event loops the connections are spread over
+const NET_THREADS: usize = 4;
+// wakes the loop up for new connections and for connections that have been written to
+const WAKE_TOKEN: Token = Token(0);
+
+pub type NetThread = Sender<NetThreadMsg>;
+
+pub enum NetThreadMsg {
+    Add(Box<Connection>),
+    Close(Reason), // Closes every connection with an ERROR line, writing out what is left
+    Stop, // Drops the connections that are left
+}
+
+// One event loop, which reads, writes and times out every connection added to it. Writers handed
+// out by new_writer wake the loop so it drains them, rather than each having a thread of its own.
+#[derive(Clone)]
+pub struct NetLoop {
+    thread: NetThread,
+    woken: Arc<Mutex<Vec<Token>>>, // Connections that have been written to since the loop last looked
+    waker: Arc<Waker>,
+    next_token: Arc<AtomicUsize>,
+    connections: Arc<AtomicUsize>, // How many the loop has, kept up to date by the loop
+    handle: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl NetLoop {
+    pub fn new(throttle: Throttle) -> io::Result<Self> {
+        let (tx, rx) = channel();
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKE_TOKEN)?);
+        let woken = Arc::new(Mutex::new(vec![]));
+        let worker_woken = woken.clone();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let worker_connections = connections.clone();
+        let handle = thread::Builder::new().name("NetThread".to_string()).spawn(move || {
+            NetWorker::new(poll, rx, worker_woken, worker_connections, throttle).run();
+        }).unwrap();
+        Ok(NetLoop{
+            thread: tx,
+            woken: woken,
+            waker: waker,
+            next_token: Arc::new(AtomicUsize::new(1)),
+            connections: connections,
+            handle: Arc::new(Mutex::new(Some(handle))),
+        })
+    }
+
+    // a Writer for a connection that is yet to be added, and what the connection needs to drain it
+    pub fn new_writer(&self) -> (Writer, WriterEnd) {
+        let token = Token(self.next_token.fetch_add(1, Ordering::SeqCst));
+        let (tx, rx) = channel();
+        let queued = Arc::new(AtomicBool::new(false));
+        let closed = Arc::new(Mutex::new(None));
+        let waker = ConnectionWaker{
+            token: token,
+            queued: queued.clone(),
+            woken: self.woken.clone(),
+            waker: self.waker.clone(),
+        };
+        let writer = Writer::new(tx, Arc::new(waker), closed.clone());
+        (writer, WriterEnd{ token: token, rx: rx, woken: queued, closed: closed })
+    }
+
+    pub fn add(&self, conn: Connection) {
+        let _ = send!(self.thread, NetThreadMsg::Add => (Box::new(conn)));
+        let _ = self.waker.wake();
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    pub fn close(&self, reason: Reason) {
+        let _ = send!(self.thread, NetThreadMsg::Close => (reason));
+        let _ = self.waker.wake();
+    }
+
+    // the thread is handed to whoever stops the loop first, for them to join
+    pub fn stop(&self) -> Option<JoinHandle<()>> {
+        let _ = self.thread.send(NetThreadMsg::Stop);
+        let _ = self.waker.wake();
+        self.handle.lock().unwrap().take()
+    }
+}
+
+struct ConnectionWaker {
+    token: Token,
+    queued: Arc<AtomicBool>,
+    woken: Arc<Mutex<Vec<Token>>>,
+    waker: Arc<Waker>,
+}
+
+impl WriterWaker for ConnectionWaker {
+    // a burst of writes only queues the connection once, until the loop gets round to it
+    fn wake(&self) {
+        if !self.queued.swap(true, Ordering::SeqCst) {
+            self.woken.lock().unwrap().push(self.token);
+            let _ = self.waker.wake();
+        }
+    }
+}
+
+impl Drop for ConnectionWaker {
+    // goes with the last clone of the Writer, whose channel is gone by then, so the loop finds out the user has ended
+    fn drop(&mut self) {
+        self.queued.store(false, Ordering::SeqCst);
+        self.wake();
+    }
+}
+
+// the event loops connections are spread over, taking turns
+#[derive(Clone)]
+pub struct NetPool {
+    loops: Vec<NetLoop>,
+    next: Arc<AtomicUsize>,
+}
+
+impl NetPool {
+    pub fn new(throttle: Throttle) -> io::Result<Self> {
+        let mut loops = vec![];
+        for _ in 0..NET_THREADS {
+            loops.push(NetLoop::new(throttle.clone())?);
+        }
+        Ok(NetPool{ loops: loops, next: Arc::new(AtomicUsize::new(0)) })
+    }
+
+    pub fn pick(&self) -> &NetLoop {
+        &self.loops[self.next.fetch_add(1, Ordering::SeqCst) % self.loops.len()]
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.loops.iter().map(|net_loop| net_loop.connection_count()).sum()
+    }
+
+    pub fn wait_for_connections(&self, deadline: Instant) {
+        while self.connection_count() > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    pub fn close(&self, reason: Reason) {
+        for net_loop in self.loops.iter() {
+            net_loop.close(reason.clone());
+        }
+    }
+
+    pub fn stop(&self) -> Vec<JoinHandle<()>> {
+        self.loops.iter().filter_map(|net_loop| net_loop.stop()).collect()
+    }
+}
+
+struct NetWorker {
+    poll: Poll,
+    rx: Receiver<NetThreadMsg>,
+    woken: Arc<Mutex<Vec<Token>>>,
+    throttle: Throttle,
+    connections: HashMap<Token, Connection>,
+    count: Arc<AtomicUsize>,
+}
+
+impl NetWorker {
+    fn new(poll: Poll, rx: Receiver<NetThreadMsg>, woken: Arc<Mutex<Vec<Token>>>, count: Arc<AtomicUsize>, throttle: Throttle) -> Self {
+        NetWorker{
+            poll: poll,
+            rx: rx,
+            woken: woken,
+            throttle: throttle,
+            connections: HashMap::new(),
+            count: count,
+        }
+    }
+
+    fn run(&mut self) {
+        let mut events = Events::with_capacity(1024);
+        loop {
+            // only connections that are draining have a deadline to be woken for
+            let timeout = match self.connections.values().any(|conn| conn.is_draining()) {
+                true => Some(Duration::from_secs(1)),
+                false => None,
+            };
+            if let Err(e) = self.poll.poll(&mut events, timeout) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                lprintln!("NetWorker Got error: {:?}", e);
+                return;
+            }
+            let mut touched = vec![];
+            for event in events.iter() {
+                let token = event.token();
+                if token == WAKE_TOKEN {
+                    continue;
+                }
+                if let Some(conn) = self.connections.get_mut(&token) {
+                    if event.is_readable() || event.is_read_closed() || event.is_error() {
+                        conn.readable();
+                    }
+                    touched.push(token);
+                }
+            }
+            if !self.add_connections(&mut touched) {
+                lprintln!("NetWorker stopping with {} connections left", self.connections.len());
+                return;
+            }
+            let woken: Vec<Token> = self.woken.lock().unwrap().drain(..).collect();
+            for token in woken {
+                if let Some(conn) = self.connections.get_mut(&token) {
+                    conn.drain_writer();
+                    touched.push(token);
+                }
+            }
+            let now = Instant::now();
+            for token in touched {
+                if let Some(conn) = self.connections.get_mut(&token) {
+                    conn.flush(self.poll.registry());
+                }
+            }
+            let done: Vec<Token> = self.connections.iter_mut().filter_map(|(token, conn)| {
+                conn.expire(now);
+                match conn.is_done() {
+                    true => Some(*token),
+                    false => None,
+                }
+            }).collect();
+            for token in done {
+                self.remove(token);
+            }
+        }
+    }
+
+    // returns false once the loop is to stop, or no handle to it is left to add anything
+    fn add_connections(&mut self, touched: &mut Vec<Token>) -> bool {
+        loop {
+            match self.rx.try_recv() {
+                Ok(NetThreadMsg::Add(mut conn)) => {
+                    let token = conn.token;
+                    if let Err(e) = conn.register(self.poll.registry()) {
+                        lprintln!("Failed to register connection from {}: {:?}", conn.info.ip, e);
+                        let _ = self.throttle.release(conn.info.ip.clone(), conn.info.class.clone());
+                        continue;
+                    }
+                    self.connections.insert(token, *conn);
+                    self.count.store(self.connections.len(), Ordering::SeqCst);
+                    touched.push(token);
+                },
+                Ok(NetThreadMsg::Close(reason)) => {
+                    for (token, conn) in self.connections.iter_mut() {
+                        conn.shut_down(&reason);
+                        touched.push(*token);
+                    }
+                },
+                Ok(NetThreadMsg::Stop) => return false,
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => return self.connections.len() > 0,
+            }
+        }
+    }
+
+    // the connection's place with the throttle is given up once it is gone from the loop
+    fn remove(&mut self, token: Token) {
+        if let Some(mut conn) = self.connections.remove(&token) {
+            conn.deregister(self.poll.registry());
+            self.count.store(self.connections.len(), Ordering::SeqCst);
+            lprintln!("Connection from {} ended", conn.info.ip);
+            let _ = self.throttle.release(conn.info.ip.clone(), conn.info.class.clone());
+        }
+    }
+}
+
+#[test]
+fn event_loop_test() {
+    use std::net::{TcpListener, TcpStream};
+    use std::io::{Read, Write, BufRead, BufReader};
+    use util::mpsc::RecvTimeoutError;
+    use net_traits::{ConnectionInfo, ReaderThreadMsg};
+    use stream::{Socket, Stream};
+
+    // nothing is admitted, so releases go nowhere
+    let (throttle_tx, _throttle_rx) = channel();
+    let net_loop = NetLoop::new(Throttle::new(throttle_tx)).unwrap();
+    let connect = |sendq| {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let socket = Socket::Tcp(listener.accept().unwrap().0);
+        let (writer, end) = net_loop.new_writer();
+        let (reader_tx, reader_rx) = channel();
+        let conn = Connection::new(socket, None, None, end, reader_tx, ConnectionInfo::default(), "irc.test".into(), sendq).unwrap();
+        net_loop.add(conn);
+        (client, writer, reader_rx)
+    };
+
+    // lines read are handed to the user, whatever it writes goes out
+    let (mut client, writer, reader_rx) = connect(65536);
+    client.write_all(b"PING :hello\r\n").unwrap();
+    let timeout = Duration::from_secs(10);
+    match reader_rx.recv_timeout(timeout).unwrap() {
+        ReaderThreadMsg::Command(cmd) => assert_eq!(cmd.command, "PING"),
+    }
+    // the connection keeps count of what it read, for STATS to ask through the writer
+    assert_eq!(writer.get_stats().unwrap().0.messages_in, 1);
+    writer.write_raw("PONG :hello\r\n".into()).unwrap();
+    let mut lines = BufReader::new(client.try_clone().unwrap());
+    let mut line = String::new();
+    lines.read_line(&mut line).unwrap();
+    assert_eq!(line, "PONG :hello\r\n");
+
+    // a client that doesn't read fills the socket buffers and then its sendq
+    let (mut client, writer, reader_rx) = connect(65536);
+    let line = format!("NOTICE * :{}\r\n", "x".repeat(1000));
+    let start = Instant::now();
+    while writer.close_reason().is_none() {
+        assert!(start.elapsed() < timeout, "sendq never exceeded");
+        for _ in 0..100 {
+            if writer.write_raw(line.clone()).is_err() {
+                break;
+            }
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(writer.close_reason(), Some("SendQ exceeded".into()));
+    // the user is told by its reader going away, and the client by the socket closing
+    assert_eq!(reader_rx.recv_timeout(timeout).err(), Some(RecvTimeoutError::Disconnected));
+    client.set_read_timeout(Some(timeout)).unwrap();
+    let mut rest = vec![];
+    client.read_to_end(&mut rest).unwrap();
+
+    // closing the loop's connections tells each client why, whatever their user is doing
+    let (mut client, _writer, reader_rx) = connect(65536);
+    net_loop.close(Reason::from("Server shutting down"));
+    assert_eq!(reader_rx.recv_timeout(timeout).err(), Some(RecvTimeoutError::Disconnected));
+    client.set_read_timeout(Some(timeout)).unwrap();
+    let mut closing = String::new();
+    client.read_to_string(&mut closing).unwrap();
+    assert_eq!(closing, "ERROR :Closing Link: [irc.test] (Server shutting down)\r\n");
+
+    // a WebSocket client that closes gets its close frame back before the socket goes
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n").unwrap();
+    let mut stream = Stream::new(Socket::Tcp(listener.accept().unwrap().0), None).unwrap();
+    let websocket = stream.accept_websocket(&[]).unwrap();
+    let (socket, _) = stream.into_parts();
+    let (_writer, end) = net_loop.new_writer();
+    let (reader_tx, reader_rx) = channel();
+    net_loop.add(Connection::new(socket, None, Some(websocket), end, reader_tx, ConnectionInfo::default(), "irc.test".into(), 65536).unwrap());
+    // a normal closure (1000), masked as client frames must be
+    client.write_all(&[0x88, 0x82, 0, 0, 0, 0, 0x03, 0xe8]).unwrap();
+    assert_eq!(reader_rx.recv_timeout(timeout).err(), Some(RecvTimeoutError::Disconnected));
+    client.set_read_timeout(Some(timeout)).unwrap();
+    let mut received = vec![];
+    client.read_to_end(&mut received).unwrap();
+    assert!(received.starts_with(b"HTTP/1.1 101"));
+    assert!(received.ends_with(b"\r\n\r\n\x88\x02\x03\xe8"));
+
+    // stopping the loop drops the connections it still has
+    let (mut client, _writer, _reader_rx) = connect(65536);
+    assert!(util::join_until(net_loop.stop().unwrap(), Instant::now() + timeout));
+    assert!(net_loop.stop().is_none());
+    client.set_read_timeout(Some(timeout)).unwrap();
+    client.read_to_end(&mut rest).unwrap();
+}
@@ -1,4 +1,3 @@
-#![feature(mpsc_select)]
 #[macro_use]
 extern crate util;
 extern crate net_traits;
@@ -6,37 +5,285 @@ extern crate user as usercomponent;
 extern crate user_traits;
 extern crate channel_traits;
 extern crate server_traits;
+extern crate server;
+extern crate rustls;
+extern crate ring;
+extern crate base64;
+extern crate mio;
+#[cfg(test)]
+extern crate rcgen;
 
-use std::net::{TcpListener};
+use std::cmp;
+use std::fmt;
 use std::thread;
+use std::thread::JoinHandle;
+use std::io;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-pub mod user;
 pub mod linefsm;
-pub mod writer_thread;
+pub mod stream;
+pub mod connection;
+pub mod event_loop;
+pub mod listener;
+pub mod websocket;
+pub mod proxy;
+pub mod throttle_thread;
 
-//pub use user::*;
 pub use linefsm::*;
-pub use writer_thread::*;
+pub use stream::*;
+pub use event_loop::*;
+pub use listener::*;
+pub use throttle_thread::*;
 
 use channel_traits::Directory;
-use user::User;
-use server_traits::Config;
+use connection::Connection;
+use websocket::WebSocket;
+use usercomponent::UserThreadFactory;
+use server_traits::{Config, ListenerBlock};
+use usercomponent::Resolver;
+use user_traits::{BanKind, UserThread};
+use net_traits::{ConnectionInfo, ReaderThread, Throttle};
+use rustls::ServerConfig;
+use util::{Reason, join_until};
 
-pub fn run(directory: Directory, config: Config) {
+// the listeners and connections of a running server, which carry on until it is shut down
+pub struct Net {
+    listeners: Vec<(Arc<Listener>, JoinHandle<()>)>,
+    stopping: Arc<AtomicBool>,
+    pool: NetPool,
+}
+
+pub fn run(directory: Directory, config: Config, resolver: Arc<dyn Resolver>) -> Net {
     lprintln!("hello world");
-    let listener = TcpListener::bind(config.get_client_bind_addr().as_str()).unwrap();
+    // connection limits are shared by all listeners
+    let throttle: Throttle = ThrottleThreadFactory::new(config.clone());
+    // once set up, every connection lives on one of these, whichever listener it came from
+    let pool = NetPool::new(throttle.clone()).unwrap();
+    let stopping = Arc::new(AtomicBool::new(false));
+    let listeners = config.get_listeners().unwrap().into_iter().map(|block| {
+        listen(block, directory.clone(), config.clone(), resolver.clone(), throttle.clone(), pool.clone(), stopping.clone())
+    }).collect();
+    Net{ listeners: listeners, stopping: stopping, pool: pool }
+}
+
+impl Net {
+    // Stops accepting, has the directory close every link and local user, and waits for what they
+    // were sent last to go out. The event loops close whatever the directory leaves open, so clients
+    // are told even when it has died. Returns false if anything was still running at the deadline.
+    pub fn shutdown(self, directory: &Directory, reason: Reason, deadline: Instant) -> bool {
+        let mut clean = true;
+        self.stopping.store(true, Ordering::SeqCst);
+        for (listener, thread) in self.listeners {
+            match listener.wake() {
+                Ok(()) => clean &= join_until(thread, deadline),
+                Err(e) => {
+                    lprintln!("Couldn't wake listener to stop it: {:?}", e);
+                    clean = false;
+                },
+            }
+        }
+        // a directory that is still there gets half the time that's left to close everything
+        let now = Instant::now();
+        let grace = match directory.shutdown(reason.clone()) {
+            Ok(()) => now + deadline.saturating_duration_since(now) / 2,
+            Err(e) => {
+                lprintln!("Directory couldn't shut down, closing connections directly: {:?}", e);
+                now
+            },
+        };
+        self.pool.wait_for_connections(grace);
+        if self.pool.connection_count() > 0 {
+            self.pool.close(reason);
+            self.pool.wait_for_connections(deadline);
+        }
+        if self.pool.connection_count() > 0 {
+            lprintln!("Giving up on {} connections that haven't closed", self.pool.connection_count());
+            clean = false;
+        }
+        for thread in self.pool.stop() {
+            clean &= join_until(thread, deadline);
+        }
+        clean
+    }
+}
+
+// every listener gets its own accept loop, they all feed into the same user creation path
+fn listen(block: ListenerBlock, directory: Directory, config: Config, resolver: Arc<dyn Resolver>, throttle: Throttle, pool: NetPool, stopping: Arc<AtomicBool>) -> (Arc<Listener>, JoinHandle<()>) {
+    lprintln!("Listening on {:?}", block);
+    let listener = Arc::new(Listener::bind(block.address.as_str()).unwrap());
+    let accepting = listener.clone();
+    let thread = thread::Builder::new().name("ListenerThread".to_string()).spawn(move || {
+        accept_loop(&accepting, block, directory, config, resolver, throttle, pool, stopping);
+    }).unwrap();
+    (listener, thread)
+}
 
-    for stream in listener.incoming() {
-        match stream {
-            Err(e) => panic!(e),
-            Ok(stream) => {
+fn accept_loop(listener: &Listener, block: ListenerBlock, directory: Directory, config: Config, resolver: Arc<dyn Resolver>, throttle: Throttle, pool: NetPool, stopping: Arc<AtomicBool>) {
+    loop {
+        let accepted = listener.accept();
+        // woken up by a connection of our own once the server is shutting down
+        if stopping.load(Ordering::SeqCst) {
+            lprintln!("No longer listening on {}", block.address);
+            return;
+        }
+        match accepted {
+            Err(e) => {
+                lprintln!("Failed to accept connection on {}: {:?}", block.address, e);
+                let _ = directory.server_notice('s', format!("Failed to accept connection on {}: {}", block.address, e));
+            },
+            Ok((mut socket, ip)) => {
+                // fetched per connection so that a REHASH'd certificate applies to new clients only
+                let tls_config = match block.tls {
+                    true => match config.get_tls_config() {
+                        Ok(Some(tls_config)) => Some(tls_config),
+                        Ok(None) => {
+                            lprintln!("Dropping TLS connection from {}, no certificate is configured", ip);
+                            continue;
+                        },
+                        Err(e) => {
+                            lprintln!("Dropping TLS connection from {}, couldn't get the certificate: {:?}", ip, e);
+                            continue;
+                        },
+                    },
+                    false => None,
+                };
+                // behind a load balancer this is the balancer's ip, the client's is checked once the PROXY header is read
+                let class = match config.match_class(ip.clone(), block.class.clone()) {
+                    Ok(class) => class,
+                    Err(e) => {
+                        lprintln!("Dropping connection from {}, couldn't match its class: {:?}", ip, e);
+                        continue;
+                    },
+                };
+                let (class, timeout) = (class.name, class.registration_timeout);
+                if !block.proxy && is_rejected(&directory, &throttle, &ip, &class, &mut socket) {
+                    continue;
+                }
+                let (port, local_port) = socket.ports();
+                let info = ConnectionInfo{
+                    ip: ip,
+                    port: port,
+                    local_port: local_port,
+                    tls: block.tls,
+                    class: class,
+                    servers: block.servers,
+                    certfp: None,
+                };
                 let directory_clone = directory.clone();
                 let config_clone = config.clone();
-                thread::Builder::new().name("ReaderThread".to_string()).spawn(move|| {
-                    let err = User::new(stream, config_clone, directory_clone).run();
-                    lprintln!("Connection ended with err: {:?}", err);
+                let block_clone = block.clone();
+                let resolver_clone = resolver.clone();
+                let throttle_clone = throttle.clone();
+                let net_loop = pool.pick().clone();
+                // the setup blocks on the client, so it gets a thread of its own until the connection joins an event loop
+                let _ = thread::Builder::new().name("SetupThread".to_string()).spawn(move|| {
+                    let mut info = info;
+                    let mut socket = socket;
+                    // a client that stalls the PROXY header or a handshake gives up its thread after the registration timeout
+                    if let Err(e) = socket.set_timeout(Some(Duration::from_secs(cmp::max(timeout, 1) as u64))) {
+                        lprintln!("Couldn't set the setup timeout: {:?}", e);
+                        return;
+                    }
+                    if block_clone.proxy {
+                        if let Err(e) = accept_proxied(&mut socket, &mut info, &block_clone, &directory_clone, &config_clone, &throttle_clone) {
+                            lprintln!("Rejected proxied connection: {:?}", e);
+                            return;
+                        }
+                    }
+                    // from here on the connection holds its place with the throttle, the event loop releases it once the connection ends
+                    let (ip, class) = (info.ip.clone(), info.class.clone());
+                    let res = open_stream(socket, tls_config, &block_clone, &mut info).and_then(|(stream, websocket)| {
+                        attach(stream, websocket, info, &net_loop, directory_clone, config_clone, resolver_clone)
+                    });
+                    if let Err(e) = res {
+                        lprintln!("Connection setup failed with err: {:?}", e);
+                        let _ = throttle_clone.release(ip, class);
+                    }
                 });
             }
         }
     }
 }
+
+// the client's address as the load balancer saw it replaces the balancer's own, so that limits,
+// hostname and ident lookups, classes, bans and cloaks all apply to the client
+fn accept_proxied(socket: &mut Socket, info: &mut ConnectionInfo, block: &ListenerBlock, directory: &Directory, config: &Config, throttle: &Throttle) -> io::Result<()> {
+    if let Some((source, destination)) = proxy::read_header(socket)? {
+        info.ip = source.ip().to_string();
+        info.port = source.port();
+        info.local_port = destination.port();
+    }
+    info.class = config.match_class(info.ip.clone(), block.class.clone()).map_err(internal_error)?.name;
+    if is_rejected(directory, throttle, &info.ip, &info.class, socket) {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "connection rejected"));
+    }
+    Ok(())
+}
+
+// runs the TLS and WebSocket handshakes, these happen on the setup thread so a slow client can't hold up the listener
+fn open_stream(socket: Socket, tls_config: Option<Arc<ServerConfig>>, block: &ListenerBlock, info: &mut ConnectionInfo) -> io::Result<(Stream, Option<WebSocket>)> {
+    let mut stream = Stream::new(socket, tls_config)?;
+    stream.complete_handshake()?;
+    info.certfp = stream.certfp();
+    let websocket = match block.websocket {
+        true => Some(stream.accept_websocket(&block.origins)?),
+        false => None,
+    };
+    Ok((stream, websocket))
+}
+
+// Creates the user and hands its connection over to an event loop, which does all reads and writes
+// from then on. The user itself still runs on a thread of its own, with its own keepalive and
+// flood timers, and so do its DNS and ident lookups. Only the socket is shared with the pool.
+fn attach(stream: Stream, websocket: Option<WebSocket>, info: ConnectionInfo, net_loop: &NetLoop, directory: Directory, config: Config, resolver: Arc<dyn Resolver>) -> io::Result<()> {
+    let (socket, tls) = stream.into_parts();
+    let class = config.get_class(info.class.clone()).map_err(internal_error)?;
+    let server_name = config.get_server_name().map_err(internal_error)?;
+    let sendq = class.sendq as usize;
+    let (writer, end) = net_loop.new_writer();
+    let (_user, reader): (UserThread, ReaderThread) = UserThreadFactory::new(writer, directory, config.clone(), info.clone(), class, server_name.clone(), resolver);
+    let conn = Connection::new(socket, tls, websocket, end, reader, info, server_name, sendq)?;
+    net_loop.add(conn);
+    Ok(())
+}
+
+// Z-Lines and connection limits are checked before any user or writer thread exists, a connection
+// that passes has been admitted by the throttle and has to be released once it ends
+fn is_rejected(directory: &Directory, throttle: &Throttle, ip: &str, class: &str, socket: &mut Socket) -> bool {
+    if is_zlined(directory, ip, socket) {
+        return true;
+    }
+    match throttle.admit(ip.into(), class.into()) {
+        Ok(Some(reason)) => {
+            lprintln!("Rejecting connection from {}: {}", ip, reason);
+            let _ = socket.write_all(format!("ERROR :Closing Link: {}[{}] ({})\r\n", ip, ip, reason).as_bytes());
+            true
+        },
+        Ok(None) => false,
+        // only this connection is dropped, the listener carries on
+        Err(e) => {
+            lprintln!("Dropping connection from {}, the throttle didn't answer: {:?}", ip, e);
+            true
+        },
+    }
+}
+
+// Z-Lines are checked before any user or writer thread exists, so the rejection is written straight to the socket
+fn is_zlined(directory: &Directory, ip: &str, socket: &mut Socket) -> bool {
+    match directory.find_ban(vec![BanKind::ZLine], "*".into(), ip.into(), ip.into()) {
+        Ok(Some(ban)) => {
+            lprintln!("Rejecting Z-Lined connection from {}", ip);
+            let _ = socket.write_all(format!("ERROR :Closing Link: {}[{}] (Z-Lined: {})\r\n", ip, ip, ban.reason).as_bytes());
+            true
+        },
+        _ => false,
+    }
+}
+
+// for errors from other actors, which the setup reports along with its own
+fn internal_error<E: fmt::Debug>(err: E) -> io::Error {
+    io::Error::other(format!("{:?}", err))
+}
@@ -21,11 +21,11 @@ impl LineFSM {
         let mut trailing = vec![];
         loop {
             match state {
-                State::Ready{mut line} => {
-                    let line = line.trim_right();
+                State::Ready{line} => {
+                    let line = line.trim_end();
                     lprintln!("<< {}", line);
 
-                    if line.chars().next() == Some(':') {
+                    if line.starts_with(':') {
                         state = State::ParsePrefix{line: line.into()};
                         continue;
                     }
@@ -48,7 +48,7 @@ impl LineFSM {
                         None => { return Err(Error::MalformedString); }
                     };
                     let mut remaining = Vec::with_capacity(iter.size_hint().0);
-                    while let Some(ref word) = iter.next() {
+                    for word in iter {
                         remaining.push(word.to_string());
                     }
                     state = if remaining.len() > 0 {
@@ -57,12 +57,12 @@ impl LineFSM {
                         State::Complete
                     }
                 },
-                State::ParseParams{mut remaining} => {
+                State::ParseParams{remaining} => {
                     params = vec![];
                     trailing = vec![];
                     let mut which = true;
                     for mut item in remaining.into_iter() {
-                        if item.chars().next() == Some(':') {
+                        if item.starts_with(':') {
                             item = item.split_at(1).1.to_owned();
                             which = false;
                         }
@@ -76,7 +76,7 @@ impl LineFSM {
                 },
                 State::Complete => {
                     return Ok(ParsedCommand{
-                        prefix: prefix.unwrap_or(String::new()),
+                        prefix: prefix.unwrap_or_default(),
                         command: command,
                         params: params,
                         trailing: trailing,
@@ -99,3 +99,18 @@ enum State {
     // complete states
     ,Complete
 }
+
+#[test]
+fn linefsm_test() {
+    let mut fsm = LineFSM::new();
+    let cmd = fsm.handle_line(":nick!user@host PRIVMSG #rust a :b c\r\n".into()).unwrap();
+    assert_eq!(cmd.prefix, "nick!user@host");
+    assert_eq!(cmd.command, "PRIVMSG");
+    assert_eq!(cmd.params, vec!["#rust", "a"]);
+    assert_eq!(cmd.trailing, vec!["b", "c"]);
+    assert_eq!(cmd.text_from(1), "a b c");
+    // the text is the same whether or not the last argument was sent as a trailing one
+    assert_eq!(fsm.handle_line("WALLOPS :hello there\r\n".into()).unwrap().text_from(0), "hello there");
+    assert_eq!(fsm.handle_line("WALLOPS hello\r\n".into()).unwrap().text_from(0), "hello");
+    assert!(fsm.handle_line("\r\n".into()).is_err());
+}
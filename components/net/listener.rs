@@ -0,0 +1,85 @@
+use std::net::{TcpListener, TcpStream, IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::fs::FileTypeExt;
+use std::fs;
+use std::io;
+
+use stream::Socket;
+
+// addresses are either host:port ("0.0.0.0:6667", "[::]:6667") or a unix socket path ("unix:/run/ircd.sock")
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub fn bind(address: &str) -> io::Result<Self> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            // a socket file left behind by a previous run would make the bind fail, anything else
+            // at the path is most likely a typo in the config and is left alone
+            match fs::symlink_metadata(path) {
+                Ok(ref metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+                Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path))),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
+                Err(e) => return Err(e),
+            }
+            Ok(Listener::Unix(UnixListener::bind(path)?))
+        } else {
+            Ok(Listener::Tcp(TcpListener::bind(address)?))
+        }
+    }
+
+    // returns the socket along with the ip it connected from
+    pub fn accept(&self) -> io::Result<(Socket, String)> {
+        match self {
+            &Listener::Tcp(ref listener) => {
+                let (stream, addr) = listener.accept()?;
+                Ok((Socket::Tcp(stream), addr.ip().to_string()))
+            },
+            &Listener::Unix(ref listener) => {
+                let (stream, _) = listener.accept()?;
+                // unix socket clients are local to this machine, treat them as loopback
+                Ok((Socket::Unix(stream), "127.0.0.1".into()))
+            },
+        }
+    }
+    // connects to the listener, so that a thread blocked accepting on it gets to check whether to stop
+    pub fn wake(&self) -> io::Result<()> {
+        match self {
+            &Listener::Tcp(ref listener) => {
+                let mut addr = listener.local_addr()?;
+                if addr.ip().is_unspecified() {
+                    addr.set_ip(match addr.ip() {
+                        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    });
+                }
+                TcpStream::connect(addr).map(|_| ())
+            },
+            &Listener::Unix(ref listener) => {
+                let addr = listener.local_addr()?;
+                match addr.as_pathname() {
+                    Some(path) => UnixStream::connect(path).map(|_| ()),
+                    None => Err(io::Error::new(io::ErrorKind::NotFound, "unnamed unix socket")),
+                }
+            },
+        }
+    }
+}
+
+#[test]
+fn unix_listener_test() {
+    use std::env;
+
+    let path = env::temp_dir().join(format!("ircd-listener-test-{}.sock", ::std::process::id()));
+    let address = format!("unix:{}", path.display());
+    // a socket left behind is replaced
+    drop(Listener::bind(&address).unwrap());
+    drop(Listener::bind(&address).unwrap());
+    // anything else is not
+    fs::remove_file(&path).unwrap();
+    fs::write(&path, "keep me").unwrap();
+    assert_eq!(Listener::bind(&address).err().map(|e| e.kind()), Some(io::ErrorKind::AlreadyExists));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "keep me");
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,142 @@
+use std::io;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str;
+
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+// the longest possible v1 header, including the CRLF
+const V1_MAX: usize = 107;
+
+// Reads the PROXY protocol header (v1 or v2) a load balancer sends ahead of the client's data and
+// returns the (source, destination) it advertises. None means the balancer connected on its own
+// behalf, eg a health check, and the connection's own addresses apply. The header is read exactly
+// so that anything after it is left for the TLS session or the LineFSM.
+pub fn read_header<S: Read>(stream: &mut S) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+    // a v1 header is at least 15 bytes, so this can't read past one
+    let mut head = [0u8; 12];
+    stream.read_exact(&mut head)?;
+    if &head[..] == V2_SIGNATURE {
+        read_v2(stream)
+    } else if head.starts_with(b"PROXY ") {
+        read_v1(stream, &head)
+    } else {
+        Err(proxy_error("missing PROXY protocol header"))
+    }
+}
+
+// PROXY TCP4 192.0.2.1 198.51.100.1 56324 6667\r\n
+fn read_v1<S: Read>(stream: &mut S, head: &[u8]) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+    let mut line = head.to_vec();
+    let mut byte = [0u8; 1];
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX {
+            return Err(proxy_error("PROXY protocol header too long"));
+        }
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    let line = str::from_utf8(&line[..line.len() - 2]).map_err(|_| proxy_error("invalid PROXY protocol header"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.get(1) {
+        Some(&"TCP4") | Some(&"TCP6") if fields.len() == 6 => {},
+        Some(&"UNKNOWN") => return Ok(None),
+        _ => return Err(proxy_error("invalid PROXY protocol header")),
+    }
+    let parse_ip = |ip: &str| ip.parse::<IpAddr>().map_err(|_| proxy_error("invalid address in PROXY protocol header"));
+    let parse_port = |port: &str| port.parse::<u16>().map_err(|_| proxy_error("invalid port in PROXY protocol header"));
+    let source = SocketAddr::new(parse_ip(fields[2])?, parse_port(fields[4])?);
+    let destination = SocketAddr::new(parse_ip(fields[3])?, parse_port(fields[5])?);
+    Ok(Some((source, destination)))
+}
+
+fn read_v2<S: Read>(stream: &mut S) -> io::Result<Option<(SocketAddr, SocketAddr)>> {
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head)?;
+    let (version, command, family) = (head[0] >> 4, head[0] & 0x0f, head[1]);
+    let len = ((head[2] as usize) << 8) | head[3] as usize;
+    if version != 2 {
+        return Err(proxy_error("unsupported PROXY protocol version"));
+    }
+    // the addresses are followed by optional TLVs, which are read and ignored
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body)?;
+    match (command, family) {
+        // LOCAL
+        (0, _) => Ok(None),
+        // PROXY over TCP/IPv4
+        (1, 0x11) if len >= 12 => {
+            let ip = |b: &[u8]| IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3]));
+            Ok(Some((
+                SocketAddr::new(ip(&body[0..4]), port(&body[8..10])),
+                SocketAddr::new(ip(&body[4..8]), port(&body[10..12])),
+            )))
+        },
+        // PROXY over TCP/IPv6
+        (1, 0x21) if len >= 36 => {
+            let ip = |b: &[u8]| {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(b);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            Ok(Some((
+                SocketAddr::new(ip(&body[0..16]), port(&body[32..34])),
+                SocketAddr::new(ip(&body[16..32]), port(&body[34..36])),
+            )))
+        },
+        // UDP, unix sockets and unspecified families don't have an address a client can be known by
+        (1, _) => Ok(None),
+        _ => Err(proxy_error("invalid PROXY protocol header")),
+    }
+}
+
+fn port(b: &[u8]) -> u16 {
+    ((b[0] as u16) << 8) | b[1] as u16
+}
+
+fn proxy_error(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[test]
+fn proxy_header_test() {
+    use std::io::Cursor;
+
+    let addrs = |src: &str, dst: &str| Some((src.parse::<SocketAddr>().unwrap(), dst.parse::<SocketAddr>().unwrap()));
+
+    let mut stream = Cursor::new(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 6667\r\nNICK alice\r\n".to_vec());
+    assert_eq!(read_header(&mut stream).unwrap(), addrs("192.0.2.1:56324", "198.51.100.1:6667"));
+    // the client's own data is left alone
+    let mut rest = String::new();
+    stream.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "NICK alice\r\n");
+
+    let mut stream = Cursor::new(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 6697\r\n".to_vec());
+    assert_eq!(read_header(&mut stream).unwrap(), addrs("[2001:db8::1]:56324", "[2001:db8::2]:6697"));
+    let mut stream = Cursor::new(b"PROXY UNKNOWN\r\n".to_vec());
+    assert_eq!(read_header(&mut stream).unwrap(), None);
+    assert!(read_header(&mut Cursor::new(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n".to_vec())).is_err());
+    assert!(read_header(&mut Cursor::new(b"NICK alice\r\nUSER a 0 * :A\r\n".to_vec())).is_err());
+    assert!(read_header(&mut Cursor::new([&b"PROXY TCP4 "[..], &[b'1'; 200][..]].concat())).is_err());
+
+    // v2, with a TLV after the addresses
+    let mut header = V2_SIGNATURE.to_vec();
+    header.extend_from_slice(&[0x21, 0x11, 0, 15, 192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x1a, 0x0b, 0x04, 0, 0]);
+    header.extend_from_slice(b"NICK alice\r\n");
+    let mut stream = Cursor::new(header);
+    assert_eq!(read_header(&mut stream).unwrap(), addrs("192.0.2.1:56324", "198.51.100.1:6667"));
+    let mut rest = String::new();
+    stream.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "NICK alice\r\n");
+
+    let mut header = V2_SIGNATURE.to_vec();
+    header.extend_from_slice(&[0x21, 0x21, 0, 36]);
+    header.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+    header.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+    header.extend_from_slice(&[0xdc, 0x04, 0x1a, 0x31]);
+    assert_eq!(read_header(&mut Cursor::new(header)).unwrap(), addrs("[2001:db8::1]:56324", "[2001:db8::2]:6705"));
+
+    // a LOCAL health check
+    let mut header = V2_SIGNATURE.to_vec();
+    header.extend_from_slice(&[0x20, 0x00, 0, 0]);
+    assert_eq!(read_header(&mut Cursor::new(header)).unwrap(), None);
+}
@@ -0,0 +1,293 @@
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::io;
+use std::time::Duration;
+use std::io::{Read, Write};
+
+use rustls::{ServerConfig, ServerConnection, Error as TlsError};
+use server::fingerprint;
+use websocket::WebSocket;
+
+// the transport underneath a Stream, as accepted by a listener
+pub enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Socket {
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        match self {
+            &Socket::Tcp(ref s) => s.take_error(),
+            &Socket::Unix(ref s) => s.take_error(),
+        }
+    }
+
+    // bounds each blocking read and write, for the handshakes before the socket goes to an event loop
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            &Socket::Tcp(ref s) => s.set_read_timeout(timeout).and_then(|_| s.set_write_timeout(timeout)),
+            &Socket::Unix(ref s) => s.set_read_timeout(timeout).and_then(|_| s.set_write_timeout(timeout)),
+        }
+    }
+
+    // (client port, local port), which unix sockets don't have
+    pub fn ports(&self) -> (u16, u16) {
+        match self {
+            &Socket::Tcp(ref s) => match (s.peer_addr(), s.local_addr()) {
+                (Ok(peer), Ok(local)) => (peer.port(), local.port()),
+                _ => (0, 0),
+            },
+            &Socket::Unix(_) => (0, 0),
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            &mut Socket::Tcp(ref mut s) => s.read(buf),
+            &mut Socket::Unix(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            &mut Socket::Tcp(ref mut s) => s.write(buf),
+            &mut Socket::Unix(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            &mut Socket::Tcp(ref mut s) => s.flush(),
+            &mut Socket::Unix(ref mut s) => s.flush(),
+        }
+    }
+}
+
+// a socket, optionally wrapped in a TLS session
+struct Transport {
+    socket: Socket,
+    tls: Option<ServerConnection>,
+}
+
+impl Transport {
+    fn new(socket: Socket, tls: Option<Arc<ServerConfig>>) -> io::Result<Self> {
+        let tls = match tls {
+            Some(config) => {
+                Some(ServerConnection::new(config).map_err(tls_error)?)
+            },
+            None => None,
+        };
+        Ok(Transport{
+            socket: socket,
+            tls: tls,
+        })
+    }
+
+    fn is_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.socket.take_error()
+    }
+
+    fn complete_handshake(&mut self) -> io::Result<()> {
+        let Transport{ref mut socket, ref mut tls} = *self;
+        if let Some(ref mut tls) = *tls {
+            while tls.is_handshaking() {
+                if receive_tls(socket, tls)? == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed during TLS handshake"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn certfp(&self) -> Option<String> {
+        self.tls.as_ref().and_then(|tls| {
+            tls.peer_certificates().and_then(|certs| certs.first()).map(|cert| fingerprint(&cert.0))
+        })
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Transport{ref mut socket, ref mut tls} = *self;
+        let tls = match *tls {
+            Some(ref mut tls) => tls,
+            None => return socket.read(buf),
+        };
+        loop {
+            match tls.reader().read(buf) {
+                Ok(n) => return Ok(n),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                Err(e) => return Err(e),
+            }
+            if receive_tls(socket, tls)? == 0 {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Transport{ref mut socket, ref mut tls} = *self;
+        match *tls {
+            Some(ref mut conn) => {
+                conn.writer().write_all(buf)?;
+                flush_tls(conn, socket)?;
+                Ok(buf.len())
+            },
+            None => socket.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush()
+    }
+}
+
+// A client connection while it is being set up, which blocks for the PROXY header and the TLS and
+// WebSocket handshakes. Once set up it is taken apart with into_parts and handed to the event loop.
+pub struct Stream {
+    transport: Transport,
+}
+
+impl Stream {
+    pub fn new(socket: Socket, tls: Option<Arc<ServerConfig>>) -> io::Result<Self> {
+        Ok(Stream{
+            transport: Transport::new(socket, tls)?,
+        })
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.transport.is_tls()
+    }
+
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.transport.take_error()
+    }
+
+    // reads from the socket until the TLS handshake is done, so the client certificate is known
+    // before the user is created. Plaintext that arrives early stays buffered for the next read.
+    pub fn complete_handshake(&mut self) -> io::Result<()> {
+        self.transport.complete_handshake()
+    }
+
+    // the SHA-256 fingerprint of the client certificate, if one was presented
+    pub fn certfp(&self) -> Option<String> {
+        self.transport.certfp()
+    }
+
+    // answers the HTTP upgrade request, from then on every IRC line is one WebSocket message
+    pub fn accept_websocket(&mut self, origins: &[String]) -> io::Result<WebSocket> {
+        WebSocket::accept(&mut self.transport, origins)
+    }
+
+    // the socket and TLS session, for the event loop to carry on with
+    pub fn into_parts(self) -> (Socket, Option<ServerConnection>) {
+        let Transport{socket, tls} = self.transport;
+        (socket, tls)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.transport.read(buf)
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.transport.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.transport.flush()
+    }
+}
+
+// waits on the socket, then feeds what arrived into the session
+fn receive_tls(socket: &mut Socket, conn: &mut ServerConnection) -> io::Result<usize> {
+    let mut incoming = [0u8; 4096];
+    let n = socket.read(&mut incoming)?;
+    if n == 0 {
+        return Ok(0);
+    }
+    let mut pending = &incoming[..n];
+    while pending.len() > 0 {
+        conn.read_tls(&mut pending)?;
+        if let Err(e) = conn.process_new_packets() {
+            // let the client know why the handshake failed before giving up
+            let _ = flush_tls(conn, socket);
+            return Err(tls_error(e));
+        }
+    }
+    // handshake messages, and any plaintext written before it completed
+    flush_tls(conn, socket)?;
+    Ok(n)
+}
+
+fn flush_tls(conn: &mut ServerConnection, socket: &mut Socket) -> io::Result<()> {
+    while conn.wants_write() {
+        conn.write_tls(socket)?;
+    }
+    Ok(())
+}
+
+pub fn tls_error(err: TlsError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[test]
+fn tls_stream_test() {
+    use std::net::TcpListener;
+    use std::thread;
+    use std::io::{BufRead, BufReader};
+    use std::convert::TryFrom;
+    use rustls::{Certificate, PrivateKey, ClientConfig, ClientConnection, RootCertStore, ServerName, StreamOwned};
+    use rcgen;
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let der = Certificate(cert.serialize_der().unwrap());
+    let key = PrivateKey(cert.serialize_private_key_der());
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![der.clone()], key)
+        .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let mut stream = Stream::new(Socket::Tcp(socket), Some(Arc::new(server_config))).unwrap();
+        assert!(stream.is_tls());
+        stream.complete_handshake().unwrap();
+        assert_eq!(stream.certfp(), None);
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        reader.get_mut().write_all(format!("echo {}", line).as_bytes()).unwrap();
+    });
+
+    let mut roots = RootCertStore::empty();
+    roots.add(&der).unwrap();
+    let client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let conn = ClientConnection::new(Arc::new(client_config), ServerName::try_from("localhost").unwrap()).unwrap();
+    let mut client = StreamOwned::new(conn, TcpStream::connect(addr).unwrap());
+    client.write_all(b"PING :hello\r\n").unwrap();
+    let mut line = String::new();
+    BufReader::new(client).read_line(&mut line).unwrap();
+    assert_eq!(line, "echo PING :hello\r\n");
+    server.join().unwrap();
+}
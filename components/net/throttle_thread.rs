@@ -0,0 +1,213 @@
+use util::mpsc::channel;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use util::{cidr_contains, mask_octets, Worker, Recovery, spawn_worker};
+use net_traits::{Throttle, ThrottleThreadMsg};
+use server_traits::{Config, ConnectionLimits, ClassBlock};
+
+pub trait ThrottleThreadFactory {
+    fn new(config: Config) -> Self;
+}
+
+impl ThrottleThreadFactory for Throttle {
+    fn new(config: Config) -> Throttle {
+        let (tx, rx) = channel();
+        spawn_worker("ThrottleThread", rx, move || ThrottleWorker::new(config.clone()));
+        Throttle::new(tx)
+    }
+}
+
+pub struct ThrottleWorker {
+    config: Config,
+    limiter: Limiter,
+}
+
+impl ThrottleWorker {
+    fn new(config: Config) -> Self {
+        ThrottleWorker{
+            config: config,
+            limiter: Default::default(),
+        }
+    }
+}
+
+impl Worker for ThrottleWorker {
+    type Msg = ThrottleThreadMsg;
+
+    fn handle_msg(&mut self, msg: ThrottleThreadMsg) -> bool {
+        match msg {
+            ThrottleThreadMsg::Admit(s, ip, class) => {
+                // fetched every time so that a REHASH applies straight away
+                let reply = match (self.config.get_connection_limits(), self.config.get_class(class)) {
+                    (Ok(limits), Ok(class)) => self.limiter.admit_at(&ip, &class, &limits, Instant::now()),
+                    (Err(e), _) | (_, Err(e)) => {
+                        lprintln!("Couldn't get the connection limits: {:?}", e);
+                        Some("Server busy, try again later".into())
+                    },
+                };
+                let _ = s.send(reply);
+            },
+            ThrottleThreadMsg::Release(ip, class) => self.limiter.release(&ip, &class),
+        }
+        false
+    }
+
+    // the counts start over, which loosens the limits until the connections there were have ended
+    fn recovery(&self) -> Recovery {
+        Recovery::Restart
+    }
+}
+
+#[derive(Debug, Default)]
+struct Limiter {
+    total: u32,
+    ips: HashMap<String, (u32, String)>, // Connections, Network
+    networks: HashMap<String, u32>,
+    classes: HashMap<String, u32>,
+    history: HashMap<String, Vec<Instant>>, // Recent connection attempts
+    throttled: HashMap<String, Instant>, // Rejected until
+}
+
+impl Limiter {
+    fn admit_at(&mut self, ip: &str, class: &ClassBlock, limits: &ConnectionLimits, now: Instant) -> Option<String> {
+        let addr: Option<IpAddr> = ip.parse().ok();
+        let network = match addr {
+            Some(ref addr) => network(addr, limits),
+            None => ip.to_string(),
+        };
+        let exempt = match addr {
+            Some(ref addr) => limits.exempt.iter().any(|entry| cidr_contains(entry, addr)),
+            None => false,
+        };
+        if !exempt {
+            if let Some(reason) = self.check(ip, &network, class, limits, now) {
+                return Some(reason);
+            }
+        }
+        self.total += 1;
+        self.ips.entry(ip.to_string()).or_insert((0, network.clone())).0 += 1;
+        *self.networks.entry(network).or_insert(0) += 1;
+        *self.classes.entry(class.name.clone()).or_insert(0) += 1;
+        None
+    }
+
+    fn check(&mut self, ip: &str, network: &str, class: &ClassBlock, limits: &ConnectionLimits, now: Instant) -> Option<String> {
+        let window = Duration::from_secs(limits.throttle_window as u64);
+        self.throttled.retain(|_, until| *until > now);
+        self.history.retain(|_, attempts| {
+            attempts.retain(|at| now.duration_since(*at) < window);
+            attempts.len() > 0
+        });
+        if self.throttled.contains_key(ip) {
+            return Some("Reconnecting too fast, throttled".into());
+        }
+        // every attempt counts towards the throttle, even ones rejected below
+        let attempts = self.history.entry(ip.to_string()).or_insert(vec![]);
+        attempts.push(now);
+        if attempts.len() as u32 > limits.throttle_count {
+            self.throttled.insert(ip.to_string(), now + Duration::from_secs(limits.throttle_duration as u64));
+            return Some("Reconnecting too fast, throttled".into());
+        }
+        if self.total >= limits.max_clients {
+            return Some("Server full".into());
+        }
+        if let Some(max_clients) = class.max_clients {
+            if self.classes.get(&class.name).cloned().unwrap_or(0) >= max_clients {
+                return Some("No more connections allowed in your connection class".into());
+            }
+        }
+        if self.ips.get(ip).map(|&(count, _)| count).unwrap_or(0) >= limits.per_ip {
+            return Some("Too many connections from your host".into());
+        }
+        if self.networks.get(network).cloned().unwrap_or(0) >= limits.per_cidr {
+            return Some("Too many connections from your network".into());
+        }
+        None
+    }
+
+    fn release(&mut self, ip: &str, class: &str) {
+        let (count, network) = match self.ips.remove(ip) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if count > 1 {
+            self.ips.insert(ip.to_string(), (count - 1, network.clone()));
+        }
+        let count = self.networks.remove(&network).unwrap_or(1);
+        if count > 1 {
+            self.networks.insert(network, count - 1);
+        }
+        let count = self.classes.remove(class).unwrap_or(1);
+        if count > 1 {
+            self.classes.insert(class.to_string(), count - 1);
+        }
+        self.total -= 1;
+    }
+}
+
+// the network an address is counted in, eg 192.0.2.0/24
+fn network(addr: &IpAddr, limits: &ConnectionLimits) -> String {
+    match addr {
+        &IpAddr::V4(ip) => {
+            let mut octets = ip.octets();
+            mask_octets(&mut octets, limits.cidr_v4);
+            format!("{}/{}", IpAddr::from(octets), limits.cidr_v4)
+        },
+        &IpAddr::V6(ip) => {
+            let mut octets = ip.octets();
+            mask_octets(&mut octets, limits.cidr_v6);
+            format!("{}/{}", IpAddr::from(octets), limits.cidr_v6)
+        },
+    }
+}
+
+#[test]
+fn connection_limits_test() {
+    let limits = ConnectionLimits{
+        max_clients: 8,
+        per_ip: 2,
+        per_cidr: 3,
+        throttle_count: 3,
+        exempt: vec!["198.51.100.0/24".into()],
+        ..Default::default()
+    };
+    let mut class = ClassBlock::default();
+    let mut limiter = Limiter::default();
+    let start = Instant::now();
+    let later = |secs| start + Duration::from_secs(secs);
+
+    assert_eq!(network(&"192.0.2.77".parse().unwrap(), &limits), "192.0.2.0/24");
+    assert_eq!(network(&"2001:db8:1:2:3::1".parse().unwrap(), &limits), "2001:db8:1:2::/64");
+    assert!(cidr_contains("2001:db8::/32", &"2001:db8:ffff::1".parse().unwrap()));
+    assert!(!cidr_contains("192.0.2.1", &"192.0.2.2".parse().unwrap()));
+
+    assert_eq!(limiter.admit_at("192.0.2.1", &class, &limits, later(0)), None);
+    assert_eq!(limiter.admit_at("192.0.2.1", &class, &limits, later(1)), None);
+    assert_eq!(limiter.admit_at("192.0.2.1", &class, &limits, later(2)), Some("Too many connections from your host".into()));
+    assert_eq!(limiter.admit_at("192.0.2.2", &class, &limits, later(2)), None);
+    assert_eq!(limiter.admit_at("192.0.2.3", &class, &limits, later(2)), Some("Too many connections from your network".into()));
+
+    // a fourth attempt within the window gets the address throttled, even once it has disconnected
+    limiter.release("192.0.2.1", "default");
+    assert_eq!(limiter.admit_at("192.0.2.1", &class, &limits, later(3)), Some("Reconnecting too fast, throttled".into()));
+    assert_eq!(limiter.admit_at("192.0.2.1", &class, &limits, later(200)), Some("Reconnecting too fast, throttled".into()));
+    assert_eq!(limiter.admit_at("192.0.2.1", &class, &limits, later(400)), None);
+
+    // exempt addresses aren't limited, but do count towards max_clients
+    for _ in 0..5 {
+        assert_eq!(limiter.admit_at("198.51.100.1", &class, &limits, later(400)), None);
+    }
+    assert_eq!(limiter.admit_at("203.0.113.1", &class, &limits, later(400)), Some("Server full".into()));
+    limiter.release("198.51.100.1", "default");
+    assert_eq!(limiter.admit_at("203.0.113.1", &class, &limits, later(400)), None);
+
+    // and so does the class limit
+    limiter.release("203.0.113.1", "default");
+    limiter.release("198.51.100.1", "default");
+    class.max_clients = Some(6);
+    assert_eq!(limiter.admit_at("203.0.113.2", &class, &limits, later(400)), Some("No more connections allowed in your connection class".into()));
+    let other = ClassBlock{ name: "other".into(), ..Default::default() };
+    assert_eq!(limiter.admit_at("203.0.113.2", &other, &limits, later(400)), None);
+}
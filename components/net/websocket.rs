@@ -0,0 +1,263 @@
+use std::io;
+use std::io::{Read, Write};
+use std::collections::HashMap;
+use std::cmp;
+use ring::digest;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// IRC lines are at most 512 bytes, plus up to 8191 bytes of message tags
+const MAX_MESSAGE: usize = 16384;
+const MAX_REQUEST: usize = 8192;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+// RFC 6455 framing for the text.ircv3.net and binary.ircv3.net subprotocols, where every IRC line
+// is carried as one message without its line ending. Once the upgrade is done it works on buffers,
+// so the event loop can feed it whatever part of a frame has arrived.
+pub struct WebSocket {
+    binary: bool,
+    // bytes of a frame that hasn't fully arrived yet
+    pending: Vec<u8>,
+    // fragments of a message that hasn't been completed yet
+    message: Vec<u8>,
+}
+
+impl WebSocket {
+    pub fn accept<S: Read + Write>(stream: &mut S, origins: &[String]) -> io::Result<Self> {
+        let request = read_request(stream)?;
+        let mut lines = request.split("\r\n");
+        let request_line = lines.next().unwrap_or("").to_string();
+        let mut headers = HashMap::new();
+        for line in lines {
+            if let Some(i) = line.find(':') {
+                headers.insert(line[..i].trim().to_lowercase(), line[i+1..].trim().to_string());
+            }
+        }
+        let header = |name: &str| headers.get(name).map(|value| value.as_str()).unwrap_or("");
+
+        if !request_line.starts_with("GET ") ||
+            !header("upgrade").to_lowercase().contains("websocket") ||
+            !header("connection").to_lowercase().contains("upgrade") ||
+            header("sec-websocket-version") != "13" ||
+            header("sec-websocket-key").len() == 0 {
+            let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n");
+            return Err(protocol_error("invalid WebSocket upgrade request"));
+        }
+        // an empty allow-list accepts any origin, eg for non-browser clients
+        if origins.len() > 0 && !origins.iter().any(|origin| origin == header("origin")) {
+            let _ = stream.write_all(b"HTTP/1.1 403 Forbidden\r\nConnection: close\r\n\r\n");
+            return Err(protocol_error("WebSocket origin is not allowed"));
+        }
+
+        let protocol = header("sec-websocket-protocol").split(',')
+            .map(|protocol| protocol.trim())
+            .find(|protocol| *protocol == "binary.ircv3.net" || *protocol == "text.ircv3.net")
+            .map(|protocol| protocol.to_string());
+        let mut response = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n", accept_key(header("sec-websocket-key")));
+        if let Some(ref protocol) = protocol {
+            response.push_str(&format!("Sec-WebSocket-Protocol: {}\r\n", protocol));
+        }
+        response.push_str("\r\n");
+        stream.write_all(response.as_bytes())?;
+
+        Ok(WebSocket{
+            // clients that don't ask for a subprotocol get text frames
+            binary: protocol == Some("binary.ircv3.net".into()),
+            pending: vec![],
+            message: vec![],
+        })
+    }
+
+    // Decodes the frames in input, appending completed messages to lines the same way they arrive
+    // over a plain socket, and answers to pings and closes to replies. Returns false once the client
+    // has closed the connection.
+    pub fn decode(&mut self, input: &[u8], lines: &mut Vec<u8>, replies: &mut Vec<u8>) -> io::Result<bool> {
+        self.pending.extend_from_slice(input);
+        loop {
+            let (len, fin, opcode, payload) = match parse_frame(&self.pending)? {
+                Some(frame) => frame,
+                None => return Ok(true),
+            };
+            self.pending.drain(..len);
+            match opcode {
+                OP_TEXT | OP_BINARY | OP_CONTINUATION => {
+                    self.message.extend(payload);
+                    if self.message.len() > MAX_MESSAGE {
+                        return Err(protocol_error("WebSocket message too long"));
+                    }
+                    if fin {
+                        let message = std::mem::take(&mut self.message);
+                        let end = message.iter().rposition(|b| *b != b'\r' && *b != b'\n').map(|i| i + 1).unwrap_or(0);
+                        lines.extend_from_slice(&message[..end]);
+                        lines.extend_from_slice(b"\r\n");
+                    }
+                },
+                OP_PING => frame(OP_PONG, &payload, replies),
+                OP_CLOSE => {
+                    // echo the status code back and treat it as the end of the connection
+                    frame(OP_CLOSE, &payload[..cmp::min(payload.len(), 2)], replies);
+                    return Ok(false);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    // every line in buf becomes one message
+    pub fn encode(&self, buf: &[u8], out: &mut Vec<u8>) {
+        for line in buf.split(|b| *b == b'\n') {
+            let line = match line.last() {
+                Some(&b'\r') => &line[..line.len() - 1],
+                _ => line,
+            };
+            if line.len() == 0 {
+                continue;
+            }
+            if self.binary {
+                frame(OP_BINARY, line, out);
+            } else {
+                // text frames must be valid UTF-8
+                frame(OP_TEXT, String::from_utf8_lossy(line).as_bytes(), out);
+            }
+        }
+    }
+}
+
+// server frames are never fragmented or masked
+fn frame(opcode: u8, payload: &[u8], out: &mut Vec<u8>) {
+    out.push(0x80 | opcode);
+    let len = payload.len();
+    if len < 126 {
+        out.push(len as u8);
+    } else if len < 65536 {
+        out.push(126);
+        out.push((len >> 8) as u8);
+        out.push(len as u8);
+    } else {
+        out.push(127);
+        for i in (0..8).rev() {
+            out.push(((len as u64) >> (i * 8)) as u8);
+        }
+    }
+    out.extend_from_slice(payload);
+}
+
+// Returns (frame length, fin, opcode, unmasked payload), or None until the whole frame has arrived
+fn parse_frame(buf: &[u8]) -> io::Result<Option<(usize, bool, u8, Vec<u8>)>> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buf[0] & 0x80 != 0;
+    let opcode = buf[0] & 0x0f;
+    let masked = buf[1] & 0x80 != 0;
+    let (len, mut offset) = match buf[1] & 0x7f {
+        126 if buf.len() >= 4 => (buf[2..4].iter().fold(0u64, |len, b| (len << 8) | *b as u64), 4),
+        127 if buf.len() >= 10 => (buf[2..10].iter().fold(0u64, |len, b| (len << 8) | *b as u64), 10),
+        126 | 127 => return Ok(None),
+        len => (len as u64, 2),
+    };
+    if !masked {
+        return Err(protocol_error("client WebSocket frames must be masked"));
+    }
+    if len > MAX_MESSAGE as u64 {
+        return Err(protocol_error("WebSocket frame too long"));
+    }
+    let len = len as usize;
+    if buf.len() < offset + 4 + len {
+        return Ok(None);
+    }
+    let mask = [buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]];
+    offset += 4;
+    let payload = buf[offset..offset + len].iter().enumerate().map(|(i, b)| b ^ mask[i % 4]).collect();
+    Ok(Some((offset + len, fin, opcode, payload)))
+}
+
+// the client sends nothing after its request until it has seen the response, so reading a byte at
+// a time up to the blank line can't swallow any frames
+fn read_request<S: Read>(stream: &mut S) -> io::Result<String> {
+    let mut request = vec![];
+    let mut byte = [0u8; 1];
+    while !request.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed during WebSocket upgrade"));
+        }
+        request.push(byte[0]);
+        if request.len() > MAX_REQUEST {
+            return Err(protocol_error("WebSocket upgrade request too long"));
+        }
+    }
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+pub fn accept_key(key: &str) -> String {
+    let hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, format!("{}{}", key, GUID).as_bytes());
+    BASE64.encode(hash.as_ref())
+}
+
+fn protocol_error(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[test]
+fn websocket_test() {
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::io::{BufRead, BufReader};
+    use stream::{Stream, Socket};
+
+    // the example handshake from RFC 6455 section 1.3
+    assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let mut stream = Stream::new(Socket::Tcp(socket), None).unwrap();
+        stream.accept_websocket(&["https://chat.example.org".into()]).unwrap()
+    });
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Protocol: text.ircv3.net\r\nOrigin: https://chat.example.org\r\n\r\n").unwrap();
+    let mut response = String::new();
+    let mut reader = BufReader::new(client);
+    while !response.ends_with("\r\n\r\n") {
+        reader.read_line(&mut response).unwrap();
+    }
+    assert!(response.starts_with("HTTP/1.1 101"));
+    assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    assert!(response.contains("Sec-WebSocket-Protocol: text.ircv3.net\r\n"));
+    let mut websocket = server.join().unwrap();
+
+    // a masked text frame, split over a continuation frame, and a ping
+    let mask = [1u8, 2, 3, 4];
+    let mut input = vec![];
+    for &(head, part) in [(0x01u8, &b"NICK "[..]), (0x80u8, &b"web"[..]), (0x89u8, &b"hi"[..])].iter() {
+        input.extend_from_slice(&[head, 0x80 | part.len() as u8]);
+        input.extend_from_slice(&mask);
+        input.extend(part.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    }
+    // frames can arrive a byte at a time
+    let (mut lines, mut replies) = (vec![], vec![]);
+    for b in input.iter() {
+        assert!(websocket.decode(&[*b], &mut lines, &mut replies).unwrap());
+    }
+    assert_eq!(lines, b"NICK web\r\n");
+    assert_eq!(replies, [0x8a, 2, b'h', b'i']);
+
+    let mut out = vec![];
+    websocket.encode(b"PING :web\r\n", &mut out);
+    assert_eq!(out[0], 0x81);
+    assert_eq!(out[1], 9);
+    assert_eq!(&out[2..], b"PING :web");
+
+    // a close frame ends the connection
+    let (mut lines, mut replies) = (vec![], vec![]);
+    assert!(!websocket.decode(&[0x88, 0x80, 0, 0, 0, 0], &mut lines, &mut replies).unwrap());
+}
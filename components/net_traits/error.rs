@@ -8,6 +8,7 @@ pub type Result<T> = result::Result<T, Error>;
 pub enum Error {
     SendError(&'static str),
     RecvError(&'static str),
+    Timeout(&'static str),
     IoError(IoError),
     MalformedString,
     UserError,
@@ -24,6 +25,7 @@ impl From<ChanError> for Error {
         match err {
             ChanError::SendError(err) => Error::SendError(err),
             ChanError::RecvError(err) => Error::RecvError(err),
+            ChanError::Timeout(err) => Error::Timeout(err),
         }
     }
 }
@@ -4,7 +4,9 @@ extern crate util;
 pub mod linefsm;
 pub mod error;
 pub mod writer_thread;
+pub mod throttle_thread;
 
 pub use error::*;
 pub use linefsm::*;
 pub use writer_thread::*;
+pub use throttle_thread::*;
@@ -8,3 +8,24 @@ pub struct ParsedCommand {
     pub trailing: Vec<String>,
 }
 
+impl ParsedCommand {
+    // the arguments from skip on as one piece of text, for commands whose last argument may or may
+    // not have been sent as a trailing one, eg WALLOPS :a b and WALLOPS a
+    pub fn text_from(&self, skip: usize) -> String {
+        let params = self.params.iter().skip(skip);
+        params.chain(self.trailing.iter()).cloned().collect::<Vec<_>>().join(" ")
+    }
+}
+
+#[test]
+fn text_from_test() {
+    let cmd = ParsedCommand{
+        prefix: "".into(),
+        command: "PRIVMSG".into(),
+        params: vec!["#rust".into(), "a".into()],
+        trailing: vec!["b".into(), "c".into()],
+    };
+    assert_eq!(cmd.text_from(0), "#rust a b c");
+    assert_eq!(cmd.text_from(1), "a b c");
+    assert_eq!(cmd.text_from(3), "b c");
+}
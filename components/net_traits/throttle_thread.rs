@@ -0,0 +1,9 @@
+use super::Error;
+
+// keeps count of the connections shared by every listener
+actor!{
+    pub struct Throttle(ThrottleThread, ThrottleThreadMsg) -> Error, timeout_ms 5000;
+    // an admitted connection has to be released once it ends, replies with the reason if the connection is rejected
+    pub req fn admit(ip: String, class: String) -> Option<String> => Admit;
+    pub send fn release(ip: String, class: String) => Release;
+}
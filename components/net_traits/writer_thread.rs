@@ -1,11 +1,49 @@
-use std::sync::mpsc::{Sender};
-use super::Result;
+use util::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::fmt;
+use std::collections::HashMap;
+use super::{Result, Error};
 use super::ParsedCommand;
+use util::{Nick, ChannelName, Hostmask, Reason, MessageText, Timestamp};
 
 pub type ReaderThread = Sender<ReaderThreadMsg>;
 
 pub enum ReaderThreadMsg {
-    Command(ParsedCommand)
+    Command(ParsedCommand),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReaderStats {
+    pub bytes_in: u64,
+    pub messages_in: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WriterStats {
+    pub sendq: usize, // Bytes waiting to be written
+    pub bytes_out: u64,
+    pub messages_out: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionStats {
+    pub name: String,
+    pub reader: ReaderStats,
+    pub writer: WriterStats,
+    pub connected_secs: u64,
+    pub commands: HashMap<String, u64>,
+}
+
+// what the listener knows about a connection before any commands are read from it
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionInfo {
+    pub ip: String,
+    pub port: u16, // The client's end of the connection, 0 for unix sockets
+    pub local_port: u16, // The listener's end of the connection
+    pub tls: bool,
+    pub class: String,
+    pub servers: bool, // Whether the listener accepts server links
+    pub certfp: Option<String>, // SHA-256 of the TLS client certificate
 }
 
 pub type WriterThread = Sender<WriterThreadMsg>;
@@ -15,36 +53,70 @@ pub enum WriterThreadMsg {
     SendRaw(String),
     Send(RPL),
     SSend(SRPL),
-    UpdateNick(String),
+    UpdateNick(Nick),
+    GetStats(Sender<(ReaderStats, WriterStats)>), // Kept by the connection, which reads as well as writes
+}
+
+// Wakes up whatever drains a connection's WriterThread, ie the event loop the connection lives on.
+// Messages are only rendered and written once it has been woken.
+pub trait WriterWaker: Send + Sync {
+    fn wake(&self);
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Writer {
     thread: WriterThread,
+    // dropped after the thread, so that whatever it wakes finds the channel closed
+    waker: Arc<dyn WriterWaker>,
+    // why the connection was dropped by the writer, eg "SendQ exceeded"
+    closed: Arc<Mutex<Option<String>>>,
+}
+
+impl fmt::Debug for Writer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Writer")
+    }
 }
 
 impl Writer {
-    pub fn new(thread: WriterThread) -> Self {
-        Writer{ thread: thread }
+    pub fn new(thread: WriterThread, waker: Arc<dyn WriterWaker>, closed: Arc<Mutex<Option<String>>>) -> Self {
+        Writer{ thread: thread, waker: waker, closed: closed }
     }
 
     pub fn write_raw(&self, msg: String) -> Result<()> {
-        try!(send!(self.thread, WriterThreadMsg::SendRaw => (msg)));
+        send!(self.thread, WriterThreadMsg::SendRaw => (msg))?;
+        self.waker.wake();
         Ok(())
     }
 
     pub fn write(&self, msg: RPL) -> Result<()> {
-        try!(send!(self.thread, WriterThreadMsg::Send => (msg)));
+        send!(self.thread, WriterThreadMsg::Send => (msg))?;
+        self.waker.wake();
         Ok(())
     }
 
     pub fn swrite(&self, msg: SRPL) -> Result<()> {
-        try!(send!(self.thread, WriterThreadMsg::SSend => (msg)));
+        send!(self.thread, WriterThreadMsg::SSend => (msg))?;
+        self.waker.wake();
         Ok(())
     }
 
-    pub fn update_nick(&self, nick: String) -> Result<()> {
-        try!(send!(self.thread, WriterThreadMsg::UpdateNick => (nick)));
+    // once the writer has given up on the connection, the reason to quit with
+    pub fn close_reason(&self) -> Option<String> {
+        self.closed.lock().unwrap().clone()
+    }
+
+    pub fn get_stats(&self) -> Result<(ReaderStats, WriterStats)> {
+        // req_rep! can't wake the event loop between sending and waiting for the reply
+        let (tx, rx) = channel();
+        send!(self.thread, WriterThreadMsg::GetStats => (tx))?;
+        self.waker.wake();
+        rx.recv().map_err(|_| Error::RecvError("WriterThreadMsg::GetStats"))
+    }
+
+    pub fn update_nick(&self, nick: Nick) -> Result<()> {
+        send!(self.thread, WriterThreadMsg::UpdateNick => (nick))?;
+        self.waker.wake();
         Ok(())
     }
 }
@@ -58,12 +130,18 @@ pub struct WriterData {
 
 #[derive(Debug)]
 pub enum SRPL {
+    Ping(String), // Token
     Pong(String), //msg
     Pass(String), // password
     Server(String, u32, String), // name, hops, desc
     ProtoCtl(Vec<ProtoOption>),
-    Nick(String, u32, String, String, String, String, String, String, String, String), // Nick, Hops, Timestamp, Username, Hostname, Servername, Servicestamp, Modes, CloakedHost, Realname)
-    Sjoin(String, String, Vec<String>), // Timestamp, Channel, Vec<Nick with modes>
+    Nick(Nick, u32, Timestamp, String, String, String, String, String, String, String), // Nick, Hops, Timestamp, Username, Hostname, Servername, Servicestamp, Modes, CloakedHost, Realname)
+    Sjoin(Timestamp, ChannelName, Vec<String>), // Timestamp, Channel, Vec<Nick with modes>
+    Wallops(Hostmask, MessageText), // Src, Msg
+    Globops(Nick, MessageText), // Src, Msg
+    TklAdd(char, String, String, String, u64, u64, String), // Type, User, Host, Set By, Expires At, Set At, Reason
+    TklDel(char, String, String, String), // Type, User, Host, Removed By
+    Squit(String, Reason), // Server, Reason
     EOS,
 }
 
@@ -101,7 +179,7 @@ impl ProtoOption {
             &ESVID => "ESVID".into(),
             &MLOCK => "MLOCK".into(),
             &EXTSWHOIS => "EXTSWHOIS".into(),
-        }.into()
+        }
     }
 }
 
@@ -117,6 +195,9 @@ impl SRPL {
                 hops=hops,
                 desc=desc,
             ),
+            &SRPL::Ping(ref token) => format!("PING :{token}",
+                token = token,
+            ),
             &SRPL::Pong(ref msg) => format!("PONG :{msg}",
                 msg = msg,
             ),
@@ -125,6 +206,10 @@ impl SRPL {
                 format!("PROTOCTL {opts}", opts = str)
             },
             &SRPL::EOS => "EOS".into(),
+            &SRPL::Squit(ref server, ref reason) => format!("SQUIT {server} :{reason}",
+                server=server,
+                reason=reason,
+            ),
             &SRPL::Nick(ref nick, ref hops, ref timestamp, ref username, ref hostname, ref servername, ref servicesstamp, ref modes, ref cloakedhost, ref realname) => {
                 let hops = hops+1;
                 format!("NICK {nick} {hops} {timestamp} {username} {hostname} {servername} {servicesstamp} {modes} {cloakedhost} :{realname}",
@@ -140,6 +225,31 @@ impl SRPL {
                     realname=realname,
                 )
             }
+            &SRPL::Wallops(ref src, ref msg) => format!(":{src} WALLOPS :{msg}",
+                src=src,
+                msg=msg,
+            ),
+            &SRPL::Globops(ref src, ref msg) => format!(":{src} GLOBOPS :{msg}",
+                src=src,
+                msg=msg,
+            ),
+            &SRPL::TklAdd(kind, ref user, ref host, ref set_by, expires_at, set_at, ref reason) => format!(":{sname} TKL + {kind} {user} {host} {set_by} {expires_at} {set_at} :{reason}",
+                sname=servername,
+                kind=kind,
+                user=user,
+                host=host,
+                set_by=set_by,
+                expires_at=expires_at,
+                set_at=set_at,
+                reason=reason,
+            ),
+            &SRPL::TklDel(kind, ref user, ref host, ref removed_by) => format!(":{sname} TKL - {kind} {user} {host} {removed_by}",
+                sname=servername,
+                kind=kind,
+                user=user,
+                host=host,
+                removed_by=removed_by,
+            ),
             &SRPL::Sjoin(ref timestamp, ref channel, ref users) => format!(":{sname} SJOIN {timestamp} {channel} :{users}",
                 sname=servername,
                 timestamp=timestamp,
@@ -164,23 +274,87 @@ pub enum RPL {
     MotdEnd,
     // NICK
     NickInUse,
+    ErroneousNickname(String), // Nick
+    AlreadyRegistered,
     NickNotFound(String),
+    NoSuchChannel(String), // Channel
+    CannotSendToChan(String), // Channel
     //NICK,
     // ping
+    Ping(String), // Token
     Pong(String),
     //CHAT
-    Privmsg(String, String), // Mask, Message
-    PrivmsgChan(String, String, String), // Mask, Chan, Message
+    Privmsg(Hostmask, MessageText), // Mask, Message
+    PrivmsgChan(Hostmask, ChannelName, MessageText), // Mask, Chan, Message
     //
-    Join(String, String), // Mask, ChannelName
-    Part(String, String, String), // Mask, ChannelName, Reason
+    Join(Hostmask, ChannelName), // Mask, ChannelName
+    Part(Hostmask, ChannelName, Reason), // Mask, ChannelName, Reason
 
     WhoReply(String),
     WhoSpcRpl(String, String), // Mask, Modes
     EndOfWho,
 
-    NameReply(String, Vec<String>), // ChannelName, Names
-    EndOfNames(String), // ChannelName
+    NameReply(ChannelName, Vec<Nick>), // ChannelName, Names
+    EndOfNames(ChannelName), // ChannelName
+
+    // user modes
+    UModeIs(String), // Modes
+    SnoMask(String), // Snomask
+    UModeUnknownFlag,
+    UsersDontMatch,
+
+    // oper
+    YoureOper,
+    LoggedIn(Hostmask, String), // Mask, Account
+    HostHidden(String), // Displayed Host
+    PasswdMismatch,
+    NoPrivileges,
+    NeedMoreParams(String), // Command
+    ServerNotice(String), // Message
+    AuthNotice(String), // Message, sent during registration
+
+    // informational
+    ISupport(Vec<String>), // Tokens
+    LuserClient(usize, usize, usize), // Visible, Invisible, Servers
+    LuserOp(usize),
+    LuserUnknown(usize),
+    LuserChannels(usize),
+    LuserMe(usize, usize), // Clients, Servers
+    LocalUsers(usize, usize), // Current, Max
+    GlobalUsers(usize, usize), // Current, Max
+    Version(String, String), // Version, Comments
+    Time(String),
+    AdminMe,
+    AdminLoc1(String),
+    AdminLoc2(String),
+    AdminEmail(String),
+    Info(String),
+    EndOfInfo,
+
+    // stats
+    StatsLinkInfo(String, usize, u64, u64, u64, u64, u64), // Name, SendQ, Sent Msgs, Sent Bytes, Recv Msgs, Recv Bytes, Seconds Open
+    StatsCommands(String, u64), // Command, Count
+    StatsCLine(String, String), // Host, Name
+    StatsOLine(String), // Name
+    StatsUptime(u64), // Seconds
+    WhoisUser(Nick, String, String, String), // Nick, User, Host, Real
+    WhoisServer(Nick, String, String), // Nick, Server, Server Info
+    WhoisOperator(Nick),
+    WhoisChannels(Nick, Vec<ChannelName>), // Nick, Channels
+    WhoisSecure(Nick),
+    WhoisCertFP(Nick, String), // Nick, Fingerprint
+    WhoisHost(Nick, String), // Nick, Real Host
+    EndOfWhois(String),
+    Rehashing(String), // Config Path
+    StatsKLine(String, String, String), // Host, User, Reason
+    StatsGLine(char, String, u64, u64, String, String), // Kind, Mask, Expires At, Set At, Set By, Reason
+    EndOfStats(char),
+    Wallops(Hostmask, MessageText), // Mask, Message
+    Globops(Nick, MessageText), // Nick, Message
+    Nick(Hostmask, Nick), // Mask, New Nick
+    Kill(Nick, Reason), // Killer, Reason
+    YoureBanned(Reason), // Reason
+    ClosingLink(Reason), // Reason
 }
 
 impl RPL {
@@ -220,6 +394,15 @@ impl RPL {
                 sname = servername,
                 nick = data.nick,
             ),
+            &RPL::ErroneousNickname(ref target) => format!(":{sname} 432 {nick} {target} :Erroneous Nickname",
+                sname = servername,
+                nick = if data.nick.is_empty() { "*" } else { &data.nick },
+                target = target,
+            ),
+            &RPL::AlreadyRegistered => format!(":{sname} 462 {nick} :You may not reregister",
+                sname = servername,
+                nick = data.nick,
+            ),
             &RPL::NickNotFound(ref target) => format!(":{sname} 401 {nick} {target} :No such nick/channel",
                 sname = servername,
                 nick = data.nick,
@@ -235,6 +418,9 @@ impl RPL {
                 chan = chan,
                 msg = msg,
             ),
+            &RPL::Ping(ref token) => format!("PING :{token}",
+                token = token,
+            ),
             &RPL::Pong(ref msg) => format!(":{sname} PONG {sname} :{msg}",
                 sname = servername,
                 msg = msg,
@@ -249,7 +435,7 @@ impl RPL {
                 reason=reason,
             ),
             &RPL::WhoReply(ref chan) => {
-                data.cur_chan = chan.clone().into();
+                data.cur_chan = chan.clone();
                 format!(":{sname} 352 {chan} %ctnf,152",
                     sname=servername,
                     chan=chan,
@@ -261,6 +447,16 @@ impl RPL {
                 mask=mask,
                 modes=modes,
             ),
+            &RPL::NoSuchChannel(ref channel) => format!(":{sname} 403 {nick} {channel} :No such channel",
+                sname = servername,
+                nick = data.nick,
+                channel = channel,
+            ),
+            &RPL::CannotSendToChan(ref channel) => format!(":{sname} 404 {nick} {channel} :Cannot send to channel",
+                sname = servername,
+                nick = data.nick,
+                channel = channel,
+            ),
             &RPL::EndOfWho => format!(":{sname} 315 {nick} {chan} :End of /WHO list.",
                 sname=servername,
                 nick=data.nick,
@@ -270,13 +466,293 @@ impl RPL {
                 sname=servername,
                 nick=data.nick,
                 channel=channel,
-                names=names.join(" "),
+                names=names.iter().map(|name| name.as_str()).collect::<Vec<_>>().join(" "),
             ),
             &RPL::EndOfNames(ref channel) => format!(":{sname} 366 {nick} {channel} :End of /NAMES list",
                 sname=servername,
                 nick=data.nick,
                 channel=channel,
             ),
+            &RPL::UModeIs(ref modes) => format!(":{sname} 221 {nick} +{modes}",
+                sname=servername,
+                nick=data.nick,
+                modes=modes,
+            ),
+            &RPL::SnoMask(ref snomask) => format!(":{sname} 008 {nick} +{snomask} :Server notice mask",
+                sname=servername,
+                nick=data.nick,
+                snomask=snomask,
+            ),
+            &RPL::UModeUnknownFlag => format!(":{sname} 501 {nick} :Unknown MODE flag",
+                sname=servername,
+                nick=data.nick,
+            ),
+            &RPL::UsersDontMatch => format!(":{sname} 502 {nick} :Cant change mode for other users",
+                sname=servername,
+                nick=data.nick,
+            ),
+            &RPL::YoureOper => format!(":{sname} 381 {nick} :You are now an IRC operator",
+                sname=servername,
+                nick=data.nick,
+            ),
+            &RPL::LoggedIn(ref mask, ref account) => format!(":{sname} 900 {nick} {mask} {account} :You are now logged in as {account}",
+                sname=servername,
+                nick=data.nick,
+                mask=mask,
+                account=account,
+            ),
+            &RPL::HostHidden(ref host) => format!(":{sname} 396 {nick} {host} :is now your displayed host",
+                sname=servername,
+                nick=data.nick,
+                host=host,
+            ),
+            &RPL::PasswdMismatch => format!(":{sname} 464 {nick} :Password incorrect",
+                sname=servername,
+                nick=data.nick,
+            ),
+            &RPL::NoPrivileges => format!(":{sname} 481 {nick} :Permission Denied- You're not an IRC operator",
+                sname=servername,
+                nick=data.nick,
+            ),
+            &RPL::NeedMoreParams(ref command) => format!(":{sname} 461 {nick} {command} :Not enough parameters",
+                sname=servername,
+                nick=data.nick,
+                command=command,
+            ),
+            &RPL::AuthNotice(ref msg) => format!(":{sname} NOTICE {nick} :*** {msg}",
+                sname=servername,
+                nick=if data.nick.len() > 0 { data.nick.as_str() } else { "*" },
+                msg=msg,
+            ),
+            &RPL::ServerNotice(ref msg) => format!(":{sname} NOTICE {nick} :*** Notice -- {msg}",
+                sname=servername,
+                nick=data.nick,
+                msg=msg,
+            ),
+            &RPL::ISupport(ref tokens) => format!(":{sname} 005 {nick} {tokens} :are supported by this server",
+                sname=servername,
+                nick=data.nick,
+                tokens=tokens.join(" "),
+            ),
+            &RPL::LuserClient(visible, invisible, servers) => format!(":{sname} 251 {nick} :There are {visible} users and {invisible} invisible on {servers} servers",
+                sname=servername,
+                nick=data.nick,
+                visible=visible,
+                invisible=invisible,
+                servers=servers,
+            ),
+            &RPL::LuserOp(opers) => format!(":{sname} 252 {nick} {opers} :operator(s) online",
+                sname=servername,
+                nick=data.nick,
+                opers=opers,
+            ),
+            &RPL::LuserUnknown(unknown) => format!(":{sname} 253 {nick} {unknown} :unknown connection(s)",
+                sname=servername,
+                nick=data.nick,
+                unknown=unknown,
+            ),
+            &RPL::LuserChannels(channels) => format!(":{sname} 254 {nick} {channels} :channels formed",
+                sname=servername,
+                nick=data.nick,
+                channels=channels,
+            ),
+            &RPL::LuserMe(clients, servers) => format!(":{sname} 255 {nick} :I have {clients} clients and {servers} servers",
+                sname=servername,
+                nick=data.nick,
+                clients=clients,
+                servers=servers,
+            ),
+            &RPL::LocalUsers(current, max) => format!(":{sname} 265 {nick} {current} {max} :Current local users {current}, max {max}",
+                sname=servername,
+                nick=data.nick,
+                current=current,
+                max=max,
+            ),
+            &RPL::GlobalUsers(current, max) => format!(":{sname} 266 {nick} {current} {max} :Current global users {current}, max {max}",
+                sname=servername,
+                nick=data.nick,
+                current=current,
+                max=max,
+            ),
+            &RPL::Version(ref version, ref comments) => format!(":{sname} 351 {nick} {version}. {sname} :{comments}",
+                sname=servername,
+                nick=data.nick,
+                version=version,
+                comments=comments,
+            ),
+            &RPL::Time(ref time) => format!(":{sname} 391 {nick} {sname} :{time}",
+                sname=servername,
+                nick=data.nick,
+                time=time,
+            ),
+            &RPL::AdminMe => format!(":{sname} 256 {nick} {sname} :Administrative info",
+                sname=servername,
+                nick=data.nick,
+            ),
+            &RPL::AdminLoc1(ref loc) => format!(":{sname} 257 {nick} :{loc}",
+                sname=servername,
+                nick=data.nick,
+                loc=loc,
+            ),
+            &RPL::AdminLoc2(ref loc) => format!(":{sname} 258 {nick} :{loc}",
+                sname=servername,
+                nick=data.nick,
+                loc=loc,
+            ),
+            &RPL::AdminEmail(ref email) => format!(":{sname} 259 {nick} :{email}",
+                sname=servername,
+                nick=data.nick,
+                email=email,
+            ),
+            &RPL::Info(ref line) => format!(":{sname} 371 {nick} :{line}",
+                sname=servername,
+                nick=data.nick,
+                line=line,
+            ),
+            &RPL::EndOfInfo => format!(":{sname} 374 {nick} :End of /INFO list",
+                sname=servername,
+                nick=data.nick,
+            ),
+            &RPL::StatsLinkInfo(ref name, sendq, sent_msgs, sent_bytes, recv_msgs, recv_bytes, open) => format!(":{sname} 211 {nick} {name} {sendq} {sent_msgs} {sent_bytes} {recv_msgs} {recv_bytes} :{open}",
+                sname=servername,
+                nick=data.nick,
+                name=name,
+                sendq=sendq,
+                sent_msgs=sent_msgs,
+                sent_bytes=sent_bytes,
+                recv_msgs=recv_msgs,
+                recv_bytes=recv_bytes,
+                open=open,
+            ),
+            &RPL::StatsCommands(ref command, count) => format!(":{sname} 212 {nick} {command} {count}",
+                sname=servername,
+                nick=data.nick,
+                command=command,
+                count=count,
+            ),
+            &RPL::StatsCLine(ref host, ref name) => format!(":{sname} 213 {nick} C {host} * {name} 0 default",
+                sname=servername,
+                nick=data.nick,
+                host=host,
+                name=name,
+            ),
+            &RPL::StatsOLine(ref name) => format!(":{sname} 243 {nick} O * * {name}",
+                sname=servername,
+                nick=data.nick,
+                name=name,
+            ),
+            &RPL::StatsUptime(secs) => format!(":{sname} 242 {nick} :Server Up {days} days {hours}:{minutes:02}:{seconds:02}",
+                sname=servername,
+                nick=data.nick,
+                days=secs / 86400,
+                hours=(secs % 86400) / 3600,
+                minutes=(secs % 3600) / 60,
+                seconds=secs % 60,
+            ),
+            &RPL::StatsKLine(ref host, ref user, ref reason) => format!(":{sname} 216 {nick} K {host} * {user} :{reason}",
+                sname=servername,
+                nick=data.nick,
+                host=host,
+                user=user,
+                reason=reason,
+            ),
+            &RPL::StatsGLine(kind, ref mask, expires_at, set_at, ref set_by, ref reason) => format!(":{sname} 223 {nick} {kind} {mask} {expires_at} {set_at} {set_by} :{reason}",
+                sname=servername,
+                nick=data.nick,
+                kind=kind,
+                mask=mask,
+                expires_at=expires_at,
+                set_at=set_at,
+                set_by=set_by,
+                reason=reason,
+            ),
+            &RPL::WhoisUser(ref target, ref user, ref host, ref real) => format!(":{sname} 311 {nick} {target} {user} {host} * :{real}",
+                sname=servername,
+                nick=data.nick,
+                target=target,
+                user=user,
+                host=host,
+                real=real,
+            ),
+            &RPL::WhoisServer(ref target, ref server, ref info) => format!(":{sname} 312 {nick} {target} {server} :{info}",
+                sname=servername,
+                nick=data.nick,
+                target=target,
+                server=server,
+                info=info,
+            ),
+            &RPL::WhoisOperator(ref target) => format!(":{sname} 313 {nick} {target} :is an IRC operator",
+                sname=servername,
+                nick=data.nick,
+                target=target,
+            ),
+            &RPL::WhoisChannels(ref target, ref channels) => format!(":{sname} 319 {nick} {target} :{channels}",
+                sname=servername,
+                nick=data.nick,
+                target=target,
+                channels=channels.iter().map(|channel| channel.as_str()).collect::<Vec<_>>().join(" "),
+            ),
+            &RPL::WhoisSecure(ref target) => format!(":{sname} 671 {nick} {target} :is using a secure connection",
+                sname=servername,
+                nick=data.nick,
+                target=target,
+            ),
+            &RPL::WhoisCertFP(ref target, ref certfp) => format!(":{sname} 276 {nick} {target} :has client certificate fingerprint {certfp}",
+                sname=servername,
+                nick=data.nick,
+                target=target,
+                certfp=certfp,
+            ),
+            &RPL::WhoisHost(ref target, ref host) => format!(":{sname} 378 {nick} {target} :is connecting from *@{host}",
+                sname=servername,
+                nick=data.nick,
+                target=target,
+                host=host,
+            ),
+            &RPL::EndOfWhois(ref target) => format!(":{sname} 318 {nick} {target} :End of /WHOIS list.",
+                sname=servername,
+                nick=data.nick,
+                target=target,
+            ),
+            &RPL::Rehashing(ref path) => format!(":{sname} 382 {nick} {path} :Rehashing",
+                sname=servername,
+                nick=data.nick,
+                path=path,
+            ),
+            &RPL::EndOfStats(letter) => format!(":{sname} 219 {nick} {letter} :End of /STATS report",
+                sname=servername,
+                nick=data.nick,
+                letter=letter,
+            ),
+            &RPL::Wallops(ref mask, ref msg) => format!(":{mask} WALLOPS :{msg}",
+                mask=mask,
+                msg=msg,
+            ),
+            &RPL::Globops(ref src, ref msg) => format!(":{sname} NOTICE {nick} :*** Global -- from {src}: {msg}",
+                sname=servername,
+                nick=data.nick,
+                src=src,
+                msg=msg,
+            ),
+            &RPL::Nick(ref mask, ref new_nick) => format!(":{mask} NICK :{new_nick}",
+                mask=mask,
+                new_nick=new_nick,
+            ),
+            &RPL::Kill(ref killer, ref reason) => format!(":{killer} KILL {nick} :{reason}",
+                killer=killer,
+                nick=data.nick,
+                reason=reason,
+            ),
+            &RPL::YoureBanned(ref reason) => format!(":{sname} 465 {nick} :You are banned from this server- {reason}",
+                sname=servername,
+                nick=data.nick,
+                reason=reason,
+            ),
+            &RPL::ClosingLink(ref reason) => format!("ERROR :Closing Link: {nick}[{sname}] ({reason})",
+                sname=servername,
+                nick=data.nick,
+                reason=reason,
+            ),
         }
     }
 }
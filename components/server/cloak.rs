@@ -0,0 +1,65 @@
+use std::net::IpAddr;
+use ring::hmac;
+
+// Cloaks are keyed hashes of the real host, so they stay the same across connections without
+// revealing the host to anyone who doesn't have the keys. Each hash also covers a shorter prefix of
+// the address, so a ban on the tail of a cloak still covers the whole /24 (or /64 for IPv6).
+pub fn cloak_host(keys: &[String], host: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, keys.join("\n").as_bytes());
+    let hash = |input: &str| {
+        let tag = hmac::sign(&key, input.as_bytes());
+        tag.as_ref()[..4].iter().map(|b| format!("{:02X}", b)).collect::<String>()
+    };
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            let o = ip.octets();
+            format!("{}.{}.{}.IP",
+                hash(host),
+                hash(&format!("{}.{}.{}", o[0], o[1], o[2])),
+                hash(&format!("{}.{}", o[0], o[1])),
+            )
+        },
+        Ok(IpAddr::V6(ip)) => {
+            let s = ip.segments();
+            format!("{}:{}:{}:IP",
+                hash(host),
+                hash(&format!("{:x}:{:x}:{:x}:{:x}", s[0], s[1], s[2], s[3])),
+                hash(&format!("{:x}:{:x}:{:x}", s[0], s[1], s[2])),
+            )
+        },
+        Err(_) => {
+            // hostnames keep their domain so users can still tell roughly where someone connects from
+            match host.find('.') {
+                Some(i) if host[i+1..].contains('.') => format!("cloak-{}{}", hash(host), &host[i..]),
+                _ => format!("cloak-{}", hash(host)),
+            }
+        },
+    }
+}
+
+#[test]
+fn cloak_host_test() {
+    let keys = vec!["first key".to_string(), "second key".to_string()];
+
+    let cloak = cloak_host(&keys, "192.0.2.1");
+    assert_eq!(cloak, cloak_host(&keys, "192.0.2.1"));
+    assert!(cloak.ends_with(".IP"));
+    assert!(!cloak.contains("192"));
+    // neighbours share the tail of their cloak
+    let neighbour = cloak_host(&keys, "192.0.2.200");
+    assert!(cloak != neighbour);
+    assert_eq!(cloak[9..], neighbour[9..]);
+    // other keys give other cloaks
+    assert!(cloak != cloak_host(&["another key".to_string()], "192.0.2.1"));
+
+    let cloak = cloak_host(&keys, "2001:db8::1");
+    assert!(cloak.ends_with(":IP"));
+    assert_eq!(cloak[9..], cloak_host(&keys, "2001:db8::2")[9..]);
+    assert_eq!(cloak_host(&keys, "0::1").len(), "XXXXXXXX:XXXXXXXX:XXXXXXXX:IP".len());
+
+    let cloak = cloak_host(&keys, "client.example.org");
+    assert!(cloak.starts_with("cloak-"));
+    assert!(cloak.ends_with(".example.org"));
+    assert!(cloak_host(&keys, "localhost").starts_with("cloak-"));
+    assert!(!cloak_host(&keys, "example.org").contains("example"));
+}
@@ -1,104 +1,276 @@
-use std::sync::mpsc::{channel, Receiver};
-use std::net::TcpStream;
-use std::thread;
-use std::path::Path; 
+use util::mpsc::channel;
+use util::{Worker, Recovery, spawn_worker};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use server_traits::*;
-use std::io;
 use std::io::prelude::*;
 use std::fs::File;
 use serde_yaml;
 use std::str;
-use std::collections::BTreeMap;
+use rustls::ServerConfig;
+use tls::{load_tls_config, normalize_fingerprint};
+use cloak::cloak_host;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
+pub struct OperBlock {
+    name: String,
+    #[serde(default)]
+    pass: Option<String>,
+    #[serde(default)]
+    certfp: Option<String>, // Authenticates by client certificate instead of password
+}
+
+impl OperBlock {
+    fn matches(&self, name: &String, pass: &Option<String>, certfp: &Option<String>) -> bool {
+        if self.name != *name {
+            return false;
+        }
+        let pass_ok = match (&self.pass, pass) {
+            (&Some(ref expected), &Some(ref pass)) => expected == pass,
+            _ => false,
+        };
+        let certfp_ok = match (&self.certfp, certfp) {
+            (&Some(ref expected), &Some(ref certfp)) => normalize_fingerprint(expected) == *certfp,
+            _ => false,
+        };
+        pass_ok || certfp_ok
+    }
+}
+
+// an account clients log in to with PASS account:password, which is all an account does so far
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccountBlock {
+    name: String,
+    pass: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct ConfigData {
     server_name: String,
-    client_bind_addr: String,
+    listeners: Vec<ListenerBlock>,
+    #[serde(default)]
+    classes: Vec<ClassBlock>,
+    #[serde(default)]
+    limits: ConnectionLimits,
     server_bind_addr: String,
-    server_pass: String,
+    #[serde(default)]
+    links: Vec<LinkBlock>,
     server_desc: String,
+    #[serde(default)]
+    admin_loc1: String,
+    #[serde(default)]
+    admin_loc2: String,
+    #[serde(default)]
+    admin_email: String,
+    #[serde(default)]
+    opers: Vec<OperBlock>,
+    #[serde(default)]
+    accounts: Vec<AccountBlock>,
+    #[serde(default)]
+    tls_cert_path: Option<String>,
+    #[serde(default)]
+    tls_key_path: Option<String>,
+    #[serde(default)]
+    cloak_keys: Vec<String>, // Secret, changing them changes every cloak
 }
 
 pub fn parse_config(file: &Path) -> ConfigData {
-    // TODO: not hardcode this
-    let mut f = File::open(file).unwrap();
-    let mut buffer = Vec::new();
-
-    f.read_to_end(&mut buffer).unwrap();
+    load_config(file).unwrap()
+}
 
-    lprintln!("Read file: {:?}", buffer);
+pub fn load_config(file: &Path) -> Result<ConfigData> {
+    let mut f = File::open(file).map_err(|e| config_error(file, e))?;
+    let mut buffer = Vec::new();
 
-    // custom derive deserialize seems to be broken, TODO: make that work
-    let mut data: BTreeMap<String, String> = BTreeMap::new();
+    f.read_to_end(&mut buffer).map_err(|e| config_error(file, e))?;
 
+    let text = str::from_utf8(&buffer).map_err(|e| config_error(file, e))?;
+    let data: ConfigData = serde_yaml::from_str(text).map_err(|e| config_error(file, e))?;
+    // not the contents, which hold the cloak keys and the oper, link and class passwords
+    lprintln!("Loaded config from {}", file.display());
 
-    data = serde_yaml::from_str(str::from_utf8(&buffer).unwrap()).unwrap();
-    lprintln!("Data: {:?}", data);
+    Ok(data)
+}
 
-    ConfigData{
-        server_name: data.get("server_name").unwrap().to_owned(),
-        client_bind_addr: data.get("client_bind_addr").unwrap().to_owned(),
-        server_bind_addr: data.get("server_bind_addr").unwrap().to_owned(),
-        server_pass: data.get("server_pass").unwrap().to_owned(),
-        server_desc: data.get("server_desc").unwrap().to_owned(),
+fn load_tls(data: &ConfigData) -> Result<Option<Arc<ServerConfig>>> {
+    match (&data.tls_cert_path, &data.tls_key_path) {
+        (&Some(ref cert_path), &Some(ref key_path)) => Ok(Some(load_tls_config(Path::new(cert_path), Path::new(key_path))?)),
+        _ => Ok(None),
     }
 }
 
+pub fn config_error<E: ToString>(path: &Path, err: E) -> Error {
+    Error::ConfigError(format!("{}: {}", path.display(), err.to_string()))
+}
+
 pub trait ConfigThreadFactory {
-    fn new(ConfigData) -> Self;
+    fn new(path: PathBuf, data: ConfigData) -> Self;
 }
 
 impl ConfigThreadFactory for ConfigThread {
-    fn new(data: ConfigData) -> ConfigThread {
+    fn new(path: PathBuf, data: ConfigData) -> ConfigThread {
         let (tx, rx) = channel();
-        thread::Builder::new().name("ConfigThread".to_string()).spawn(move || {
-            ConfigWorker::new(rx, data).run();
+        let tls = load_tls(&data).unwrap();
+        let last_good = Arc::new(Mutex::new((data, tls)));
+        spawn_worker("ConfigThread", rx, move || {
+            ConfigWorker::new(path.clone(), last_good.clone())
         });
         tx
     }
 }
 
+// the config as of startup or the last successful REHASH, what a restarted worker comes back up with
+type LastGood = Arc<Mutex<(ConfigData, Option<Arc<ServerConfig>>)>>;
+
 pub struct ConfigWorker {
-    rx: Receiver<ConfigThreadMsg>,
-    data: ConfigData
+    path: PathBuf,
+    data: ConfigData,
+    tls: Option<Arc<ServerConfig>>,
+    last_good: LastGood,
 }
 
 impl ConfigWorker {
-    fn new(rx: Receiver<ConfigThreadMsg>, data: ConfigData) -> Self{
+    fn new(path: PathBuf, last_good: LastGood) -> Self{
+        let (data, tls) = last_good.lock().unwrap_or_else(|e| e.into_inner()).clone();
         ConfigWorker{
-            rx: rx,
+            path: path,
             data: data,
+            tls: tls,
+            last_good: last_good,
         }
     }
+}
 
-    fn run(&mut self) {
-        loop {
-            lselect!{
-                msg = self.rx => {
-                    match msg {
-                        Ok(msg) => {
-                            if self.handle_msg(msg) {
-                                return
-                            }
-                        },
-                        Err(e) => {
-                            lprintln!("UserThread Got error: {:?}", e);
-                            return;
-                        }
-                    }
-                },
-            }
-        }
-    }
+impl Worker for ConfigWorker {
+    type Msg = ConfigThreadMsg;
 
     fn handle_msg(&mut self, msg: ConfigThreadMsg) -> bool {
         match msg {
-            ConfigThreadMsg::GetServerName(s) => s.send(self.data.server_name.clone()),
-            ConfigThreadMsg::GetClientBindAddr(s) => s.send(self.data.client_bind_addr.clone()),
-            ConfigThreadMsg::GetServerBindAddr(s) => s.send(self.data.server_bind_addr.clone()),
-            ConfigThreadMsg::GetServerPass(s) => s.send(self.data.server_pass.clone()),
-            ConfigThreadMsg::GetServerDesc(s) => s.send(self.data.server_desc.clone()),
+            ConfigThreadMsg::GetServerName(s) => { let _ = s.send(self.data.server_name.clone()); },
+            ConfigThreadMsg::GetListeners(s) => { let _ = s.send(self.data.listeners.clone()); },
+            ConfigThreadMsg::GetClass(s, name) => { let _ = s.send(self.get_class(name)); },
+            ConfigThreadMsg::MatchClass(s, ip, listener_class) => {
+                let _ = match self.data.classes.iter().find(|class| class.allows(&ip)).cloned() {
+                    Some(class) => s.send(class),
+                    None => s.send(self.get_class(listener_class)),
+                };
+            },
+            ConfigThreadMsg::GetConnectionLimits(s) => { let _ = s.send(self.data.limits.clone()); },
+            ConfigThreadMsg::GetServerBindAddr(s) => { let _ = s.send(self.data.server_bind_addr.clone()); },
+            ConfigThreadMsg::GetLinkNames(s) => {
+                let _ = s.send(self.data.links.iter().map(|link| link.name.clone()).collect());
+            },
+            ConfigThreadMsg::GetLink(s, name) => {
+                let _ = s.send(self.data.links.iter().find(|link| link.name.to_lowercase() == name.to_lowercase()).cloned());
+            },
+            ConfigThreadMsg::GetServerDesc(s) => { let _ = s.send(self.data.server_desc.clone()); },
+            ConfigThreadMsg::GetAdminLoc1(s) => { let _ = s.send(self.data.admin_loc1.clone()); },
+            ConfigThreadMsg::GetAdminLoc2(s) => { let _ = s.send(self.data.admin_loc2.clone()); },
+            ConfigThreadMsg::GetAdminEmail(s) => { let _ = s.send(self.data.admin_email.clone()); },
+            ConfigThreadMsg::CheckOper(s, name, pass, certfp) => {
+                let _ = s.send(self.data.opers.iter().any(|oper| oper.matches(&name, &pass, &certfp)));
+            },
+            ConfigThreadMsg::CheckAccount(s, name, pass) => {
+                let _ = s.send(self.data.accounts.iter().any(|account| account.name == name && account.pass == pass));
+            },
+            ConfigThreadMsg::GetOperNames(s) => {
+                let _ = s.send(self.data.opers.iter().map(|oper| oper.name.clone()).collect());
+            },
+            ConfigThreadMsg::GetTlsConfig(s) => { let _ = s.send(self.tls.clone()); },
+            ConfigThreadMsg::CloakHost(s, host) => {
+                let _ = s.send(match self.data.cloak_keys.len() {
+                    0 => None,
+                    _ => Some(cloak_host(&self.data.cloak_keys, &host)),
+                });
+            },
+            ConfigThreadMsg::Rehash(s) => { let _ = s.send(self.rehash()); },
         };
         false
     }
+
+    // made again from the config last loaded, the file may have changed without a REHASH since
+    fn recovery(&self) -> Recovery {
+        Recovery::Restart
+    }
+}
+
+impl ConfigWorker {
+    // classes that aren't configured get the defaults
+    fn get_class(&self, name: String) -> ClassBlock {
+        let class = self.data.classes.iter().find(|class| class.name == name).cloned();
+        class.unwrap_or(ClassBlock{ name: name, ..Default::default() })
+    }
+
+    // connections keep the TLS session they were accepted with, only new ones see a reloaded certificate
+    fn rehash(&mut self) -> Result<String> {
+        let data = load_config(&self.path)?;
+        let tls = load_tls(&data)?;
+        *self.last_good.lock().unwrap_or_else(|e| e.into_inner()) = (data.clone(), tls.clone());
+        self.data = data;
+        self.tls = tls;
+        Ok(self.path.display().to_string())
+    }
+}
+
+#[test]
+fn config_restart_test() {
+    use std::{env, fs};
+    use std::time::Duration;
+
+    // the real worker, with a message to make it panic on top of its own
+    enum TestMsg {
+        Config(ConfigThreadMsg),
+        Boom,
+    }
+
+    struct TestWorker(ConfigWorker);
+
+    impl Worker for TestWorker {
+        type Msg = TestMsg;
+
+        fn handle_msg(&mut self, msg: TestMsg) -> bool {
+            match msg {
+                TestMsg::Config(msg) => self.0.handle_msg(msg),
+                TestMsg::Boom => panic!("injected"),
+            }
+        }
+
+        fn recovery(&self) -> Recovery {
+            self.0.recovery()
+        }
+    }
+
+    let path = env::temp_dir().join(format!("ircd-config-test-{}.yaml", ::std::process::id()));
+    let write = |name: &str| {
+        fs::write(&path, format!("server_name: {}\nlisteners: []\nserver_bind_addr: 127.0.0.1:0\nserver_desc: test\n", name)).unwrap();
+    };
+    write("first.test");
+    let last_good: LastGood = Arc::new(Mutex::new((load_config(&path).unwrap(), None)));
+    let (tx, rx) = channel();
+    let worker_path = path.clone();
+    spawn_worker("ConfigThread", rx, move || TestWorker(ConfigWorker::new(worker_path.clone(), last_good.clone())));
+    let server_name = || {
+        let (s, r) = channel();
+        tx.send(TestMsg::Config(ConfigThreadMsg::GetServerName(s))).unwrap();
+        r.recv_timeout(Duration::from_secs(5)).unwrap()
+    };
+    let rehash = || {
+        let (s, r) = channel();
+        tx.send(TestMsg::Config(ConfigThreadMsg::Rehash(s))).unwrap();
+        r.recv_timeout(Duration::from_secs(5)).unwrap()
+    };
+
+    // a restarted worker has what was last REHASHed, not what the file has come to say since
+    write("second.test");
+    rehash().unwrap();
+    write("third.test");
+    tx.send(TestMsg::Boom).unwrap();
+    assert_eq!(server_name(), "second.test");
+
+    // nor a file that no longer loads
+    fs::write(&path, "server_name: [").unwrap();
+    assert!(rehash().is_err());
+    tx.send(TestMsg::Boom).unwrap();
+    assert_eq!(server_name(), "second.test");
+    fs::remove_file(&path).unwrap();
 }
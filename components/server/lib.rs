@@ -1,7 +1,3 @@
-#![feature(plugin)]
-#![feature(serde_macros)]
-#![feature(custom_derive)]
-#![feature(mpsc_select)]
 #[macro_use]
 extern crate util;
 extern crate user_traits;
@@ -9,14 +5,24 @@ extern crate net_traits;
 extern crate channel_traits;
 extern crate server_traits;
 
+#[macro_use]
 extern crate serde;
 extern crate serde_yaml;
+extern crate rustls;
+extern crate rustls_pemfile;
+extern crate ring;
+#[cfg(test)]
+extern crate rcgen;
 
 
 pub mod server_thread;
 pub mod config_thread;
 pub mod virtual_user_thread;
+pub mod tls;
+pub mod cloak;
 
 pub use server_thread::*;
 pub use config_thread::*;
 pub use virtual_user_thread::*;
+pub use tls::*;
+pub use cloak::*;
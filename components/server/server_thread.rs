@@ -1,11 +1,11 @@
-use std::sync::mpsc::{channel, Receiver, Select};
-use std::net::TcpStream;
-use std::thread;
-
-use user_traits::{User, Mask, UserThread};
-use channel_traits::{Directory};
-use net_traits::{Writer,ParsedCommand,ReaderThreadMsg,SRPL};
-use server_traits::Config;
+use util::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
+
+use user_traits::{Mask, UserThread, Ban, BanKind};
+use channel_traits::{Directory, DirectoryId};
+use net_traits::{Writer,ParsedCommand,ReaderThreadMsg,ConnectionStats,SRPL};
+use server_traits::{Config, ClassBlock, LinkBlock, ServerLink, ServerThreadMsg};
+use util::{Keepalive, KeepaliveEvent, Nick, ChannelName, Hostmask, MessageText, Timestamp};
 use super::{VirtualUserThreadFactory, VirtualUserChannels};
 
 #[derive(Debug, Clone)]
@@ -16,20 +16,37 @@ enum State {
 
 pub struct ServerWorker {
     rx: Receiver<ReaderThreadMsg>,
+    srx: Receiver<ServerThreadMsg>,
+    link: ServerLink,
+    link_id: Option<DirectoryId>,
+    block: LinkBlock,
     writer: Writer,
     directory: Directory,
     config: Config,
+    server_name: String,
     state: State,
+    remote_name: String,
+    connected_at: Instant,
+    keepalive: Keepalive,
     users: Vec<VirtualUserChannels>,
 }
 impl ServerWorker {
-    pub fn new(rx: Receiver<ReaderThreadMsg>, writer: Writer, directory: Directory, config: Config) -> Self {
+    pub fn new(rx: Receiver<ReaderThreadMsg>, writer: Writer, directory: Directory, config: Config, class: ClassBlock, server_name: String, block: LinkBlock) -> Self {
+        let (stx, srx) = channel();
         ServerWorker{
             rx: rx,
+            srx: srx,
+            link: ServerLink::new(stx),
+            link_id: None,
+            remote_name: block.name.clone(),
+            block: block,
             writer: writer,
             directory: directory,
             config: config,
+            server_name: server_name,
             state: State::Sync,
+            connected_at: Instant::now(),
+            keepalive: Keepalive::new(Duration::from_secs(class.ping_freq as u64), Duration::from_secs(class.ping_timeout as u64)),
             users: vec![],
         }
     }
@@ -39,14 +56,22 @@ impl ServerWorker {
     
         self.introduce();
         self.sync();
+        self.link_id = self.directory.new_server(self.link.clone()).ok();
+        self.event_loop();
+        if let Some(id) = self.link_id {
+            let _ = self.directory.destroy_server(id);
+        }
+        let _ = self.directory.server_notice('l', format!("Lost link with {}", self.remote_name));
+    }
 
-        enum SelectState {
-            SelfUser(usize),
-            SelfRx,
-        };
-
+    fn event_loop(&mut self) {
         loop {
-            lselect!(
+            lselect_timeout!(
+                self.keepalive.next_timeout_ms() => {
+                    if self.check_keepalive() {
+                        return;
+                    }
+                },
                 msg = self.rx => {
                     match msg {
                         Ok(msg) => {
@@ -60,17 +85,30 @@ impl ServerWorker {
                         }
                     };
                 },
+                msg = self.srx => {
+                    match msg {
+                        Ok(msg) => {
+                            if self.handle_server_msg(msg) {
+                                return;
+                            }
+                        }
+                        Err(e) => {
+                            lprintln!("ServerWorker Got error: {:?}", e);
+                            return;
+                        }
+                    };
+                },
             );
         };
     }
 
     fn introduce(&mut self) {
-        self.writer.swrite(SRPL::Pass(self.config.get_server_pass()));
+        let _ = self.writer.swrite(SRPL::Pass(self.block.pass.clone()));
 
         {
             use net_traits::ProtoOption::*;
-            self.writer.swrite(SRPL::ProtoCtl(vec![
-                EAUTH(self.config.get_server_name()),
+            let _ = self.writer.swrite(SRPL::ProtoCtl(vec![
+                EAUTH(self.server_name.clone()),
                 //SID( ... TODO: this),
                 NOQUIT,
                 NICKv2,
@@ -85,10 +123,10 @@ impl ServerWorker {
             ]));
         }
         
-        self.writer.swrite(SRPL::Server(
-            self.config.get_server_name(),
+        let _ = self.writer.swrite(SRPL::Server(
+            self.server_name.clone(),
             1, // hops always 1 for self
-            self.config.get_server_desc(),
+            self.config.get_server_desc().unwrap_or_default(),
         ));
     }
 
@@ -97,16 +135,16 @@ impl ServerWorker {
             let users = self.directory.get_users().unwrap();
             for user in users.into_iter() {
                 let mask = user.get_mask().unwrap();
-                self.writer.swrite(SRPL::Nick(
+                let _ = self.writer.swrite(SRPL::Nick(
                     mask.nick.clone(),
-                    mask.hops.clone(),
-                    mask.timestamp.clone(),
+                    mask.hops,
+                    mask.timestamp,
                     mask.user.clone(),
-                    mask.host.clone(),
+                    mask.real_host.clone(),
                     mask.servername.clone(),
                     "0".into(), // services stamp
-                    "".into(), // modes
-                    "*".into(), // cloaked host
+                    if mask.host != mask.real_host { "+x".into() } else { "".into() }, // modes
+                    mask.cloaked_host.clone().unwrap_or("*".into()), // cloaked host
                     mask.real.clone(),
                 ));
             }
@@ -114,86 +152,206 @@ impl ServerWorker {
         {
             let chans = self.directory.get_channels().unwrap();
             let chans = chans.into_iter().map(|chan| {
-                let chan_created_at = 0;
+                let chan_created_at = Timestamp(0);
                 let chan_name = chan.get_name().unwrap();
                 let users = chan.get_users().unwrap().into_iter().map(|user| {
-                    user.get_mask().unwrap().nick
+                    user.get_mask().unwrap().nick.to_string()
                 }).collect();
                 (chan_name, chan_created_at, users)
             });
             for (chan_name, chan_created_at, users) in chans {
-                self.writer.swrite(SRPL::Sjoin(chan_created_at.to_string(), chan_name, users));
+                let _ = self.writer.swrite(SRPL::Sjoin(chan_created_at, chan_name, users));
             }
         }
-        self.writer.swrite(SRPL::EOS);
+        let _ = self.writer.swrite(SRPL::EOS);
     }
     
+    fn handle_server_msg(&mut self, msg: ServerThreadMsg) -> bool {
+        match msg {
+            ServerThreadMsg::Wallops(src, msg) => {
+                let _ = self.writer.swrite(SRPL::Wallops(src, msg));
+            },
+            ServerThreadMsg::Globops(src, msg) => {
+                let _ = self.writer.swrite(SRPL::Globops(src, msg));
+            },
+            ServerThreadMsg::AddBan(ban) => {
+                let _ = self.writer.swrite(SRPL::TklAdd(
+                    ban.kind.letter(),
+                    ban.user.clone(),
+                    ban.host.clone(),
+                    ban.set_by.clone(),
+                    ban.expires_at.unwrap_or(0),
+                    ban.set_at,
+                    ban.reason.clone(),
+                ));
+            },
+            ServerThreadMsg::RemoveBan(kind, mask, removed_by) => {
+                let ban = Ban::new(kind, &mask, 0, "".into(), removed_by);
+                let _ = self.writer.swrite(SRPL::TklDel(kind.letter(), ban.user, ban.host, ban.set_by));
+            },
+            ServerThreadMsg::GetStats(s) => {
+                let (reader_stats, writer_stats) = self.writer.get_stats().unwrap_or_default();
+                let _ = s.send(ConnectionStats{
+                    name: self.remote_name.clone(),
+                    reader: reader_stats,
+                    writer: writer_stats,
+                    connected_secs: self.connected_at.elapsed().as_secs(),
+                    commands: Default::default(),
+                });
+            },
+            ServerThreadMsg::Exit(reason) => {
+                let _ = self.writer.swrite(SRPL::Squit(self.server_name.clone(), reason));
+                return true;
+            },
+        }
+        false
+    }
+
+    // the same liveness check clients get, so a link that silently died doesn't linger
+    fn check_keepalive(&mut self) -> bool {
+        match self.keepalive.poll() {
+            KeepaliveEvent::Ping(token) => {
+                let _ = self.writer.swrite(SRPL::Ping(token));
+                false
+            },
+            KeepaliveEvent::Timeout(secs) => {
+                let _ = self.directory.server_notice('l', format!("No response from {}, closing link (Ping timeout: {} seconds)", self.remote_name, secs));
+                true
+            },
+            _ => false,
+        }
+    }
+
     fn handle_msg(&mut self, msg: ReaderThreadMsg) -> bool {
         return match msg {
             ReaderThreadMsg::Command(cmd) => {
+                self.keepalive.received();
                 self.handle_command(cmd)
             },
         };
     }
 
     
-    fn handle_command(&mut self, mut cmd: ParsedCommand) -> bool{
+    fn handle_command(&mut self, cmd: ParsedCommand) -> bool{
         match (self.state.clone(), cmd.command.to_uppercase().as_str()) {
             (_, "SMO") => {
                 lprintln!("SMO -> {:?}", cmd.trailing.join(" "));
             },
             (_, "PING") => {
-                self.writer.swrite(SRPL::Pong(cmd.params.clone().join(" ") + cmd.trailing.clone().join(" ").as_str()));
+                let _ = self.writer.swrite(SRPL::Pong(cmd.params.clone().join(" ") + cmd.trailing.clone().join(" ").as_str()));
+            },
+            (_, "PONG") => {
+                if let Some(token) = cmd.params.iter().chain(cmd.trailing.iter()).last() {
+                    self.keepalive.pong(token);
+                }
             },
             (State::Sync, "EOS") => {
                 self.state = State::Connected;
+                let _ = self.directory.server_notice('l', format!("Link with {} established", self.remote_name));
             },
             (_, "NICK") => {
                 lprintln!("GOT VIRTUAL USER");
-                let mask = Mask::new(
-                    cmd.params[0].clone(), // Nick
+                let (nick, timestamp) = match (Nick::parse(&cmd.params[0]), Timestamp::parse(&cmd.params[2])) {
+                    (Some(nick), Some(timestamp)) => (nick, timestamp),
+                    _ => {
+                        lprintln!("Ignoring NICK with a bad nick or timestamp: {:?}", cmd);
+                        return false;
+                    },
+                };
+                let mut mask = Mask::new(
+                    nick,
                     cmd.params[3].clone(), // User
                     cmd.params[4].clone(), // host
                     cmd.trailing.join(".clone() "), // real
                     cmd.params[1].parse().unwrap(), // hops
-                    cmd.params[2].clone(), // timestamp
+                    timestamp,
                     cmd.params[5].clone(), // servername
                 );
+                mask.cloaked_host = cmd.params.get(8).cloned().and_then(|host| if host == "*" { None } else { Some(host) });
+                if cmd.params.get(7).map(|modes| modes.contains('x')).unwrap_or(false) {
+                    if let Some(ref cloak) = mask.cloaked_host {
+                        mask.host = cloak.clone();
+                    }
+                }
                 let vu = <UserThread as VirtualUserThreadFactory>::new(self.directory.clone(), self.config.clone(), mask);
                 self.users.push(vu);
             },
+            (_, "WALLOPS") => {
+                let msg = cmd.text_from(0);
+                match Hostmask::parse(&cmd.prefix) {
+                    Some(src) => { let _ = self.directory.wallops(self.link_id, src, msg.into()); },
+                    None => { lprintln!("Ignoring WALLOPS with a bad source: {:?}", cmd); },
+                }
+            },
+            (_, "GLOBOPS") => {
+                let msg = cmd.text_from(0);
+                match Nick::parse(&cmd.prefix) {
+                    Some(src) => { let _ = self.directory.globops(self.link_id, src, msg.into()); },
+                    None => { lprintln!("Ignoring GLOBOPS with a bad source: {:?}", cmd); },
+                }
+            },
+            (_, "TKL") => {
+                // only G-Lines are shared between servers
+                if cmd.params.len() < 5 || cmd.params[1] != "G" {
+                    lprintln!("Ignoring TKL: {:?}", cmd);
+                    return false;
+                }
+                let mask = format!("{}@{}", cmd.params[2], cmd.params[3]);
+                let set_by = cmd.params[4].clone();
+                if cmd.params[0] == "+" && cmd.params.len() >= 7 {
+                    let mut ban = Ban::new(BanKind::GLine, &mask, 0, cmd.trailing.join(" "), set_by);
+                    ban.expires_at = match cmd.params[5].parse() {
+                        Ok(0) | Err(_) => None,
+                        Ok(expires_at) => Some(expires_at),
+                    };
+                    ban.set_at = cmd.params[6].parse().unwrap_or(ban.set_at);
+                    let _ = self.directory.add_ban(self.link_id, ban);
+                } else if cmd.params[0] == "-" {
+                    let _ = self.directory.remove_ban(self.link_id, BanKind::GLine, mask, set_by);
+                }
+            },
             (_, "SJOIN") => {
-                let timestamp = cmd.params[0].clone();
-                let channel = cmd.params[1].clone();
+                let _timestamp = cmd.params[0].clone();
+                let channel = match ChannelName::parse(&cmd.params[1]) {
+                    Some(channel) => channel,
+                    None => return false,
+                };
                 let nicks = cmd.trailing.clone();
                 for nick in nicks.into_iter() {
-                    let (nick, modes) = parse_nick(nick);
-                    
+                    let nick = match parse_nick(nick) {
+                        (Some(nick), _modes) => nick,
+                        _ => continue,
+                    };
+
                     let maybe_user = self.users.iter().find(|user| user.user_thread.get_mask().unwrap().nick == nick);
                     
                     if let Some(user) = maybe_user {
-                        user.vuser_thread.join(channel.clone());
+                        let _ = user.vuser_thread.join(channel.clone());
                     }
                 }
             },
             (_, "PART") => {
-                let nick = cmd.prefix;
-                let chan = cmd.params[0].clone();
+                let (nick, chan) = match (Nick::parse(&cmd.prefix), ChannelName::parse(&cmd.params[0])) {
+                    (Some(nick), Some(chan)) => (nick, chan),
+                    _ => return false,
+                };
                 let maybe_user = self.users.iter().find(|user| user.user_thread.get_mask().unwrap().nick == nick);
 
                 if let Some(user) = maybe_user {
-                    user.vuser_thread.part(chan);
+                    let _ = user.vuser_thread.part(chan);
                 }
             },
             (_, "PRIVMSG") => {
-                let nick = cmd.prefix;
-                let chan = cmd.params[0].clone();
-                let msg = cmd.trailing.join(" ");
+                let (nick, chan) = match (Nick::parse(&cmd.prefix), ChannelName::parse(&cmd.params[0])) {
+                    (Some(nick), Some(chan)) => (nick, chan),
+                    _ => return false,
+                };
+                let msg = MessageText::from(cmd.trailing.join(" "));
 
                 let maybe_user = self.users.iter().find(|user| user.user_thread.get_mask().unwrap().nick == nick);
 
                 if let Some(user) = maybe_user {
-                    user.vuser_thread.privmsg_chan(chan, msg);
+                    let _ = user.vuser_thread.privmsg_chan(chan, msg);
                 }
 
             },
@@ -205,12 +363,12 @@ impl ServerWorker {
     }
 }
 
-fn parse_nick(nick: String) -> (String, Vec<char>) {
-    let mut chars = nick.chars();
-    let mut is_flags = true;
+fn parse_nick(nick: String) -> (Option<Nick>, Vec<char>) {
+    let chars = nick.chars();
+    let is_flags = true;
     let mut outnick = String::new();
     let mut modes = vec![];
-    while let Some(next) = chars.next() {
+    for next in chars {
         match (is_flags, next) {
             (true, '@') => {
                 modes.push('o');
@@ -220,5 +378,5 @@ fn parse_nick(nick: String) -> (String, Vec<char>) {
             },
         }
     };
-    (outnick, modes)
+    (Nick::parse(&outnick), modes)
 }
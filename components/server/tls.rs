@@ -0,0 +1,126 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::io::BufReader;
+use std::fs::File;
+use std::time::SystemTime;
+use rustls::{ServerConfig, Certificate, PrivateKey, DistinguishedName, Error as TlsError};
+use rustls::server::{ClientCertVerifier, ClientCertVerified};
+use rustls_pemfile;
+use rustls_pemfile::Item;
+use ring::digest;
+use server_traits::*;
+use config_thread::config_error;
+
+pub fn load_tls_config(cert_path: &Path, key_path: &Path) -> Result<Arc<ServerConfig>> {
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(Arc::new(AnyClientCert))
+        .with_single_cert(certs, key)
+        .map_err(|e| config_error(cert_path, e))?;
+    Ok(Arc::new(config))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| config_error(path, e))?);
+    let certs = rustls_pemfile::certs(&mut reader).map_err(|e| config_error(path, e))?;
+    if certs.len() == 0 {
+        return Err(config_error(path, "no certificates found"));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| config_error(path, e))?);
+    loop {
+        match rustls_pemfile::read_one(&mut reader).map_err(|e| config_error(path, e))? {
+            Some(Item::RSAKey(key)) | Some(Item::PKCS8Key(key)) | Some(Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => {},
+            None => return Err(config_error(path, "no private key found")),
+        }
+    }
+}
+
+// Client certificates are optional and are not checked against any CA, they are only used to
+// identify a client by their fingerprint (CertFP). rustls still verifies the client holds the key.
+struct AnyClientCert;
+
+impl ClientCertVerifier for AnyClientCert {
+    fn client_auth_mandatory(&self) -> bool {
+        false
+    }
+
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(&self, _end_entity: &Certificate, _intermediates: &[Certificate], _now: SystemTime) -> ::std::result::Result<ClientCertVerified, TlsError> {
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+// the SHA-256 fingerprint of a DER certificate, as lowercase hex
+pub fn fingerprint(der: &[u8]) -> String {
+    digest::digest(&digest::SHA256, der).as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// fingerprints are often pasted with colons and in uppercase, eg from openssl x509 -fingerprint
+pub fn normalize_fingerprint(fp: &str) -> String {
+    fp.chars().filter(|c| *c != ':').collect::<String>().to_lowercase()
+}
+
+#[test]
+fn client_certfp_test() {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::convert::TryFrom;
+    use rustls::{ClientConfig, ClientConnection, ServerConnection, RootCertStore, ServerName};
+    use rcgen;
+
+    assert_eq!(fingerprint(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    assert_eq!(normalize_fingerprint("BA:78:16:BF"), "ba7816bf");
+
+    let server_cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let dir = env::temp_dir();
+    let cert_path = dir.join(format!("ircd-certfp-test-{}.crt", ::std::process::id()));
+    let key_path = dir.join(format!("ircd-certfp-test-{}.key", ::std::process::id()));
+    fs::write(&cert_path, server_cert.serialize_pem().unwrap()).unwrap();
+    fs::write(&key_path, server_cert.serialize_private_key_pem()).unwrap();
+    let server_config = load_tls_config(&cert_path, &key_path).unwrap();
+    fs::remove_file(&cert_path).unwrap();
+    fs::remove_file(&key_path).unwrap();
+
+    let client_cert = rcgen::generate_simple_self_signed(vec!["client".to_string()]).unwrap();
+    let client_der = client_cert.serialize_der().unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(&Certificate(server_cert.serialize_der().unwrap())).unwrap();
+    let client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_client_auth_cert(vec![Certificate(client_der.clone())], PrivateKey(client_cert.serialize_private_key_der()))
+        .unwrap();
+
+    let mut server = ServerConnection::new(server_config).unwrap();
+    let mut client = ClientConnection::new(Arc::new(client_config), ServerName::try_from("localhost").unwrap()).unwrap();
+    client.writer().write_all(b"hello").unwrap();
+    // shuttle the handshake between both ends in memory
+    while server.is_handshaking() || client.is_handshaking() || client.wants_write() {
+        let mut buf = vec![];
+        while client.wants_write() {
+            client.write_tls(&mut buf).unwrap();
+        }
+        server.read_tls(&mut &buf[..]).unwrap();
+        server.process_new_packets().unwrap();
+        let mut buf = vec![];
+        while server.wants_write() {
+            server.write_tls(&mut buf).unwrap();
+        }
+        client.read_tls(&mut &buf[..]).unwrap();
+        client.process_new_packets().unwrap();
+    }
+
+    let peer = server.peer_certificates().unwrap();
+    assert_eq!(fingerprint(&peer[0].0), fingerprint(&client_der));
+}
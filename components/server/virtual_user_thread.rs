@@ -1,13 +1,15 @@
-use std::sync::mpsc::{channel, Receiver, Select, Handle};
-use user_traits::{User,UserThread,UserThreadMsg,Mask};
+use util::mpsc::{channel, Receiver};
+use user_traits::{User,UserThread,UserThreadMsg,Mask,Whois};
+use user_traits::Error as UserError;
 use channel_traits::{Directory, DirectoryEntry, ChannelEntry};
 use server_traits::Config;
 use std::thread;
 use server_traits::{VirtualUser, VirtualUserThreadMsg};
+use util::ChannelName;
 
 #[derive(Debug)]
 struct StoredChannel {
-    name: String,
+    name: ChannelName,
     thread: ChannelEntry,
 }
 
@@ -17,7 +19,7 @@ pub struct VirtualUserChannels {
 }
 
 pub trait VirtualUserThreadFactory {
-    fn new(Directory, Config, Mask) -> VirtualUserChannels;
+    fn new(directory: Directory, config: Config, mask: Mask) -> VirtualUserChannels;
 }
 
 impl VirtualUserThreadFactory for UserThread {
@@ -25,8 +27,8 @@ impl VirtualUserThreadFactory for UserThread {
         let (utx,urx) = channel();
         let (vtx,vrx) = channel();
         let user = User::new(utx.clone());
-        let entry = directory.new_user(user.clone()).unwrap();
-        thread::Builder::new().name("VirtualUserThread".to_string()).spawn(move || {
+        let entry = directory.new_user(user.clone(), false).unwrap();
+        let _ = thread::Builder::new().name("VirtualUserThread".to_string()).spawn(move || {
             VirtualUserWorker::new(urx, vrx, entry, directory, config, mask).run();
         });
         VirtualUserChannels{
@@ -40,7 +42,9 @@ struct VirtualUserWorker {
     urx: Receiver<UserThreadMsg>,
     vrx: Receiver<VirtualUserThreadMsg>,
     directory: Directory,
+    #[allow(dead_code)]
     config: Config,
+    #[allow(dead_code)] // only held, dropping it removes the user from the directory
     directory_entry: DirectoryEntry,
     channels: Vec<StoredChannel>,
     mask: Mask,
@@ -97,32 +101,67 @@ impl VirtualUserWorker {
         lprintln!("GOT USER MSG: {:?}", msg);
         match msg {
             UserThreadMsg::GetMask(s) => {
-                s.send(Ok(self.mask.clone()));
+                let _ = s.send(Ok(self.mask.clone()));
             },
-            UserThreadMsg::Exit => {
+            UserThreadMsg::GetStats(s) => {
+                // virtual users are carried by a server link, they have no connection of their own
+                let _ = s.send(Err(UserError::InvalidState));
+            },
+            UserThreadMsg::GetWhois(s) => {
+                let _ = s.send(Ok(Whois{
+                    mask: self.mask.clone(),
+                    modes: vec![],
+                    channels: self.channels.iter().map(|c| c.name.clone()).collect(),
+                    certfp: None,
+                }));
+            },
+            UserThreadMsg::Exit(_reason) => {
+                // only local users are told to exit, these go with their server link
                 return true
             },
-            UserThreadMsg::JoinSelf(chan) => {
+            UserThreadMsg::JoinSelf(_chan) => {
                 // nothing to do, this is when the channel thread announces that you have joined, but sending this event is the remote server's job
             },
-            UserThreadMsg::Privmsg(nick, msg) => {
+            UserThreadMsg::Privmsg(_nick, _msg) => {
                 // nothing to do, ^^^
             },
-            UserThreadMsg::PrivmsgChan(nick, chan, msg) => {
+            UserThreadMsg::PrivmsgChan(_nick, _chan, _msg) => {
                 // nothing to do, ^^^
             },
-            UserThreadMsg::JoinOther(chan, nick) => {
+            UserThreadMsg::JoinOther(_mask, _chan) => {
+                // nothing to do ^^^
+            },
+            UserThreadMsg::PartSelf(_chan, _reason) => {
+                // nothing to do ^^^
+            },
+            UserThreadMsg::PartOther(_mask, _chan, _reason) => {
                 // nothing to do ^^^
             },
-            UserThreadMsg::PartSelf(chan, reason) => {
+            UserThreadMsg::TransmitNames(_chan, _names) => {
                 // nothing to do ^^^
             },
-            UserThreadMsg::PartOther(mask, chan, reason) => {
+            UserThreadMsg::ServerNotice(_msg) => {
                 // nothing to do ^^^
             },
-            UserThreadMsg::TransmitNames(chan, names) => {
+            UserThreadMsg::Wallops(_src, _msg) => {
+                // nothing to do, WALLOPS are relayed to the remote server by the directory
+            },
+            UserThreadMsg::Globops(_src, _msg) => {
+                // nothing to do ^^^
+            },
+            UserThreadMsg::CheckBan(_ban) => {
+                // nothing to do, bans are enforced by the server the user is connected to
+            },
+            UserThreadMsg::HostResolved(_host) => {
+                // nothing to do, the remote server resolved the host before introducing the user
+            },
+            UserThreadMsg::IdentResolved(_user) => {
                 // nothing to do ^^^
             },
+            UserThreadMsg::Kill(killer, reason) => {
+                // TODO: propagate the KILL to the remote server
+                lprintln!("Cannot KILL virtual user {} ({} ({}))", self.mask.nick, killer, reason);
+            },
         }
         false
     }
@@ -140,7 +179,7 @@ impl VirtualUserWorker {
                 let maybe_chan = self.channels.iter().find(|&schan| schan.name == chan);
 
                 if let Some(chan) = maybe_chan {
-                    chan.thread.privmsg(self.mask.nick.clone(), msg);
+                    let _ = chan.thread.privmsg(self.mask.for_privmsg(), msg);
                 }
 
             },
@@ -151,15 +190,15 @@ impl VirtualUserWorker {
         false
     }
 
-    pub fn part(&mut self, chan: String) {
-        let maybe_chan = self.channels.iter().enumerate().find(|&(i, ref schan)| schan.name == chan).map(|(i, ref schan)| i);
+    pub fn part(&mut self, chan: ChannelName) {
+        let maybe_chan = self.channels.iter().enumerate().find(|&(_i, schan)| schan.name == chan).map(|(i, _schan)| i);
         if let Some(i) = maybe_chan {
             lprintln!("Swap removing channel: {:?}", chan);
-            let chan = self.channels.swap_remove(i);
+            let _chan = self.channels.swap_remove(i);
         }
     }
 
-    pub fn join(&mut self, chan: String) {
+    pub fn join(&mut self, chan: ChannelName) {
         match self.directory.get_channel_by_name(chan.clone(), self.mask.nick.clone()) {
             Ok(channel) => {
                 match self.directory.get_user_by_nick(self.mask.nick.clone()) {
@@ -168,7 +207,7 @@ impl VirtualUserWorker {
                         match channel.join(user) {
                             Ok(entry) => {
                                 lprintln!("VIRTUAL USER ADDING: {:?}", entry);
-                                entry.update_mask(self.mask.nick.clone());
+                                entry.update_mask(self.mask.for_privmsg());
                                 self.channels.push(StoredChannel{
                                     name: chan.clone(),
                                     thread: entry,
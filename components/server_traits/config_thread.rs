@@ -1,41 +1,260 @@
-use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::net::IpAddr;
+use rustls::ServerConfig;
+use util::cidr_contains;
+use user_traits::wildcard_match;
+use super::Error;
 
-pub type ConfigThread = Sender<ConfigThreadMsg>;
+fn default_class() -> String {
+    "default".into()
+}
 
-pub enum ConfigThreadMsg {
-    GetServerName(Sender<String>),
-    GetClientBindAddr(Sender<String>),
-    GetServerBindAddr(Sender<String>),
-    GetServerPass(Sender<String>),
-    GetServerDesc(Sender<String>),
+#[derive(Debug, Clone, Deserialize)]
+pub struct ListenerBlock {
+    pub address: String,
+    #[serde(default)]
+    pub tls: bool,
+    #[serde(default = "default_class")]
+    pub class: String,
+    #[serde(default)]
+    pub servers: bool, // Whether server links may connect here
+    #[serde(default)]
+    pub websocket: bool,
+    #[serde(default)]
+    pub origins: Vec<String>, // Origins allowed to open a WebSocket, any if empty
+    #[serde(default)]
+    pub proxy: bool, // Whether connections start with a PROXY protocol header, only for a trusted load balancer
 }
 
-#[derive(Clone)]
-pub struct Config {
-    thread: ConfigThread,
+fn default_true() -> bool {
+    true
 }
 
-impl Config {
-    pub fn new(thread: ConfigThread) -> Self {
-        Config{ thread: thread }
-    }
+fn default_ping_freq() -> u32 {
+    120
+}
+
+fn default_ping_timeout() -> u32 {
+    60
+}
+
+fn default_registration_timeout() -> u32 {
+    30
+}
+
+fn default_flood_burst() -> u32 {
+    10
+}
 
-    pub fn get_server_name(&self) -> String {
-        req_rep!(self.thread, ConfigThreadMsg::GetServerName => ()).unwrap()
+fn default_flood_limit() -> u32 {
+    30
+}
+
+fn default_sendq() -> u32 {
+    1048576
+}
+
+fn default_dns_timeout() -> u32 {
+    5
+}
+
+fn default_ident_timeout() -> u32 {
+    3
+}
+
+// Settings shared by every connection in this class. A connection is put in the first class that
+// allows its address, or else in its listener's class. Hostnames aren't known when connections are
+// accepted, so classes are matched by address only, a client without a hostname or ident can
+// instead be refused by its class once the lookups are done.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClassBlock {
+    pub name: String,
+    #[serde(default)]
+    pub allow: Vec<String>, // Addresses, networks (192.0.2.0/24) or address masks (192.0.2.*) put in this class on any listener
+    #[serde(default)]
+    pub max_clients: Option<u32>, // Connections allowed in this class at once
+    #[serde(default = "default_true")]
+    pub dns: bool, // Whether to look up the client's hostname
+    #[serde(default)]
+    pub require_dns: bool, // Whether clients whose hostname can't be found are refused, implies dns
+    #[serde(default = "default_dns_timeout")]
+    pub dns_timeout: u32, // Seconds to wait for the hostname before using the ip instead
+    #[serde(default = "default_true")]
+    pub ident: bool, // Whether to ask the client's ident server for its username
+    #[serde(default)]
+    pub require_ident: bool, // Whether clients without an ident response are refused, implies ident. Unix sockets have none to ask
+    #[serde(default = "default_ident_timeout")]
+    pub ident_timeout: u32, // Seconds to wait for the ident server
+    #[serde(default = "default_ping_freq")]
+    pub ping_freq: u32, // Seconds a connection may be idle before it is sent a PING
+    #[serde(default = "default_ping_timeout")]
+    pub ping_timeout: u32, // Seconds to wait for the PONG
+    #[serde(default = "default_registration_timeout")]
+    pub registration_timeout: u32, // Seconds a client has to complete NICK and USER
+    #[serde(default = "default_flood_burst")]
+    pub flood_burst: u32, // Seconds of penalty a client may send before its commands are delayed
+    #[serde(default = "default_flood_limit")]
+    pub flood_limit: u32, // Seconds of penalty at which a client is disconnected for Excess Flood
+    #[serde(default)]
+    pub flood_exempt: bool, // Eg for trusted bots, opers are always exempt
+    #[serde(default = "default_sendq")]
+    pub sendq: u32, // Bytes that may be waiting to be written before the connection is dropped
+    #[serde(default)]
+    pub password: Option<String>, // Clients in this class have to send it with PASS before registering
+}
+
+impl Default for ClassBlock {
+    fn default() -> Self {
+        ClassBlock{
+            name: default_class(),
+            allow: vec![],
+            max_clients: None,
+            dns: true,
+            require_dns: false,
+            dns_timeout: default_dns_timeout(),
+            ident: true,
+            require_ident: false,
+            ident_timeout: default_ident_timeout(),
+            ping_freq: default_ping_freq(),
+            ping_timeout: default_ping_timeout(),
+            registration_timeout: default_registration_timeout(),
+            flood_burst: default_flood_burst(),
+            flood_limit: default_flood_limit(),
+            flood_exempt: false,
+            sendq: default_sendq(),
+            password: None,
+        }
     }
+}
 
-    pub fn get_client_bind_addr(&self) -> String{
-       req_rep!(self.thread, ConfigThreadMsg::GetClientBindAddr => ()).unwrap()
+impl ClassBlock {
+    pub fn allows(&self, ip: &str) -> bool {
+        match ip.parse::<IpAddr>() {
+            Ok(addr) => self.allow.iter().any(|entry| cidr_contains(entry, &addr) || wildcard_match(entry, ip)),
+            Err(_) => false,
+        }
     }
 
-    pub fn get_server_bind_addr(&self) -> String {
-        req_rep!(self.thread, ConfigThreadMsg::GetServerBindAddr => ()).unwrap()
+    pub fn looks_up_dns(&self) -> bool {
+        self.dns || self.require_dns
     }
 
-    pub fn get_server_pass(&self) -> String {
-        req_rep!(self.thread, ConfigThreadMsg::GetServerPass => ()).unwrap()
+    pub fn looks_up_ident(&self) -> bool {
+        self.ident || self.require_ident
     }
-    pub fn get_server_desc(&self) -> String {
-        req_rep!(self.thread, ConfigThreadMsg::GetServerDesc => ()).unwrap()
+}
+
+// a server allowed to link with this one, the password is sent and expected in both directions
+#[derive(Debug, Clone, Deserialize)]
+pub struct LinkBlock {
+    pub name: String,
+    pub pass: String,
+}
+
+fn default_max_clients() -> u32 {
+    1024
+}
+
+fn default_per_ip() -> u32 {
+    5
+}
+
+fn default_per_cidr() -> u32 {
+    20
+}
+
+fn default_cidr_v4() -> u8 {
+    24
+}
+
+fn default_cidr_v6() -> u8 {
+    64
+}
+
+fn default_throttle_count() -> u32 {
+    5
+}
+
+fn default_throttle_window() -> u32 {
+    60
+}
+
+fn default_throttle_duration() -> u32 {
+    300
+}
+
+// checked as connections are accepted, before any threads are created for them
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConnectionLimits {
+    #[serde(default = "default_max_clients")]
+    pub max_clients: u32,
+    #[serde(default = "default_per_ip")]
+    pub per_ip: u32, // Concurrent connections from one address
+    #[serde(default = "default_per_cidr")]
+    pub per_cidr: u32, // Concurrent connections from one network, as sized by cidr_v4 and cidr_v6
+    #[serde(default = "default_cidr_v4")]
+    pub cidr_v4: u8,
+    #[serde(default = "default_cidr_v6")]
+    pub cidr_v6: u8,
+    #[serde(default = "default_throttle_count")]
+    pub throttle_count: u32, // Connections from one address allowed per throttle_window
+    #[serde(default = "default_throttle_window")]
+    pub throttle_window: u32, // Seconds
+    #[serde(default = "default_throttle_duration")]
+    pub throttle_duration: u32, // Seconds an address that reconnects too fast is rejected for
+    #[serde(default)]
+    pub exempt: Vec<String>, // Addresses or networks (192.0.2.0/24) no limits apply to
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        ConnectionLimits{
+            max_clients: default_max_clients(),
+            per_ip: default_per_ip(),
+            per_cidr: default_per_cidr(),
+            cidr_v4: default_cidr_v4(),
+            cidr_v6: default_cidr_v6(),
+            throttle_count: default_throttle_count(),
+            throttle_window: default_throttle_window(),
+            throttle_duration: default_throttle_duration(),
+            exempt: vec![],
+        }
     }
 }
+
+actor!{
+    pub struct Config(ConfigThread, ConfigThreadMsg) -> Error, timeout_ms 5000;
+    pub req fn get_server_name() -> String => GetServerName;
+    pub req fn get_listeners() -> Vec<ListenerBlock> => GetListeners;
+    // classes that aren't configured get the defaults
+    pub req fn get_class(name: String) -> ClassBlock => GetClass;
+    // the class a new connection from ip on a listener with listener_class is put in
+    pub req fn match_class(ip: String, listener_class: String) -> ClassBlock => MatchClass;
+    pub req fn get_connection_limits() -> ConnectionLimits => GetConnectionLimits;
+    pub req fn get_server_bind_addr() -> String => GetServerBindAddr;
+    pub req fn get_link(name: String) -> Option<LinkBlock> => GetLink;
+    pub req fn get_link_names() -> Vec<String> => GetLinkNames;
+    pub req fn get_server_desc() -> String => GetServerDesc;
+    pub req fn get_admin_loc1() -> String => GetAdminLoc1;
+    pub req fn get_admin_loc2() -> String => GetAdminLoc2;
+    pub req fn get_admin_email() -> String => GetAdminEmail;
+    pub req fn check_oper(name: String, pass: Option<String>, certfp: Option<String>) -> bool => CheckOper;
+    pub req fn check_account(name: String, pass: String) -> bool => CheckAccount;
+    pub req fn get_oper_names() -> Vec<String> => GetOperNames;
+    pub req fn get_tls_config() -> Option<Arc<ServerConfig>> => GetTlsConfig;
+    // None when no cloak keys are configured
+    pub req fn cloak_host(host: String) -> Option<String> => CloakHost;
+    // re-reads the config file, the previous config is kept if the new one can't be loaded, the reply is the path of the reloaded file
+    pub try_req fn rehash() -> String => Rehash;
+}
+
+#[test]
+fn class_allows_test() {
+    let class = ClassBlock{ allow: vec!["192.0.2.0/24".into(), "198.51.100.*".into(), "2001:db8::1".into()], ..Default::default() };
+    assert!(class.allows("192.0.2.7"));
+    assert!(class.allows("198.51.100.20"));
+    assert!(class.allows("2001:db8::1"));
+    assert!(!class.allows("198.51.101.20"));
+    assert!(!class.allows("2001:db8::2"));
+    assert!(!class.allows("not an ip"));
+}
@@ -7,8 +7,10 @@ pub type Result<T> = result::Result<T, Error>;
 pub enum Error {
     SendError(&'static str),
     RecvError(&'static str),
+    Timeout(&'static str),
     MalformedString,
     InvalidState,
+    ConfigError(String),
 }
 
 impl From<ChanError> for Error {
@@ -16,6 +18,7 @@ impl From<ChanError> for Error {
         match err {
             ChanError::SendError(err) => Error::SendError(err),
             ChanError::RecvError(err) => Error::RecvError(err),
+            ChanError::Timeout(err) => Error::Timeout(err),
         }
     }
 }
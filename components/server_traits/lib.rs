@@ -1,5 +1,11 @@
 #[macro_use]
 extern crate util;
+extern crate net_traits;
+extern crate user_traits;
+extern crate rustls;
+
+#[macro_use]
+extern crate serde;
 
 pub mod server_thread;
 pub mod config_thread;
@@ -1,11 +1,15 @@
-/*
-use std::sync::mpsc::{channel, Sender};
-use util::*;
-use super::Result;
-
-pub type ServerThread = Sender<ServerThreadMsg>;
-
-pub enum ServerThreadMsg {
+use super::Error;
+use net_traits::ConnectionStats;
+use user_traits::{Ban, BanKind};
+use util::{Nick, Hostmask, Reason, MessageText};
 
+actor!{
+    pub struct ServerLink(ServerThread, ServerThreadMsg) -> Error, timeout_ms 5000;
+    pub send fn wallops(src: Hostmask, msg: MessageText) => Wallops;
+    pub send fn globops(src: Nick, msg: MessageText) => Globops;
+    pub req fn get_stats() -> ConnectionStats => GetStats;
+    pub send fn add_ban(ban: Ban) => AddBan;
+    pub send fn remove_ban(kind: BanKind, mask: String, removed_by: String) => RemoveBan;
+    // the server is going away, the link is SQUIT with the reason
+    pub send fn exit(reason: Reason) => Exit;
 }
-*/
@@ -0,0 +1,163 @@
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use util::mpsc::channel;
+use std::thread;
+use std::mem;
+use std::ptr;
+use std::ffi::CStr;
+use libc;
+
+use user_traits::User;
+
+pub trait Resolver: Send + Sync {
+    // the PTR name for an address
+    fn reverse(&self, ip: IpAddr) -> Option<String>;
+    // the addresses a name resolves to
+    fn forward(&self, host: &str) -> Vec<IpAddr>;
+}
+
+// resolves through the system's resolver (getnameinfo/getaddrinfo), so /etc/hosts is honoured
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn reverse(&self, ip: IpAddr) -> Option<String> {
+        let mut host = [0 as libc::c_char; 1025];
+        let ret = unsafe {
+            match SocketAddr::new(ip, 0) {
+                SocketAddr::V4(addr) => {
+                    let mut sin: libc::sockaddr_in = mem::zeroed();
+                    sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                    sin.sin_addr = libc::in_addr{ s_addr: u32::from(*addr.ip()).to_be() };
+                    libc::getnameinfo(&sin as *const _ as *const libc::sockaddr, mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                        host.as_mut_ptr(), host.len() as libc::socklen_t, ptr::null_mut(), 0, libc::NI_NAMEREQD)
+                },
+                SocketAddr::V6(addr) => {
+                    let mut sin6: libc::sockaddr_in6 = mem::zeroed();
+                    sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                    sin6.sin6_addr.s6_addr = addr.ip().octets();
+                    libc::getnameinfo(&sin6 as *const _ as *const libc::sockaddr, mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                        host.as_mut_ptr(), host.len() as libc::socklen_t, ptr::null_mut(), 0, libc::NI_NAMEREQD)
+                },
+            }
+        };
+        if ret != 0 {
+            return None;
+        }
+        unsafe { CStr::from_ptr(host.as_ptr()) }.to_str().ok().map(|host| host.to_string())
+    }
+
+    fn forward(&self, host: &str) -> Vec<IpAddr> {
+        match (host, 0).to_socket_addrs() {
+            Ok(addrs) => addrs.map(|addr| addr.ip()).collect(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+// The PTR name is only trusted if it resolves back to the same address, otherwise anyone
+// controlling their reverse zone could claim any hostname.
+pub fn lookup_host(resolver: &dyn Resolver, ip: &str) -> Option<String> {
+    let ip: IpAddr = match ip.parse() {
+        Ok(ip) => ip,
+        Err(_) => return None,
+    };
+    let host = match resolver.reverse(ip) {
+        Some(host) => host.trim_end_matches('.').to_lowercase(),
+        None => return None,
+    };
+    if !is_valid_host(&host) {
+        return None;
+    }
+    if resolver.forward(&host).contains(&ip) {
+        Some(host)
+    } else {
+        None
+    }
+}
+
+// Runs the lookup off the user thread and reports back with UserThreadMsg::HostResolved. A
+// resolver that takes longer than the timeout is abandoned and reported as a failure.
+pub fn start_lookup(resolver: Arc<dyn Resolver>, ip: String, timeout_ms: u32, user: User) {
+    let _ = thread::Builder::new().name("DnsThread".to_string()).spawn(move || {
+        let (tx, rx) = channel();
+        let _ = thread::Builder::new().name("DnsLookupThread".to_string()).spawn(move || {
+            let _ = tx.send(lookup_host(&*resolver, &ip));
+        });
+        lselect_timeout!{
+            timeout_ms => {
+                let _ = user.host_resolved(None);
+            },
+            host = rx => {
+                let _ = user.host_resolved(host.unwrap_or(None));
+            },
+        }
+    });
+}
+
+// a hostname ends up in every mask, it can't contain anything that would break the protocol
+fn is_valid_host(host: &str) -> bool {
+    host.len() > 0 && host.len() <= 63 &&
+        host.chars().all(|c| c.is_alphanumeric() || c == '.' || c == '-') &&
+        !host.starts_with('.') && !host.starts_with('-')
+}
+
+// the ip is used as the host when a lookup fails, a leading ':' would break the protocol
+pub fn host_from_ip(ip: &str) -> String {
+    if ip.starts_with(':') {
+        format!("0{}", ip)
+    } else {
+        ip.to_string()
+    }
+}
+
+#[test]
+fn lookup_host_test() {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use user_traits::UserThreadMsg;
+
+    struct FakeResolver {
+        ptr: HashMap<IpAddr, String>,
+        a: HashMap<String, Vec<IpAddr>>,
+        delay_ms: u64,
+    }
+
+    impl Resolver for FakeResolver {
+        fn reverse(&self, ip: IpAddr) -> Option<String> {
+            thread::sleep(Duration::from_millis(self.delay_ms));
+            self.ptr.get(&ip).cloned()
+        }
+        fn forward(&self, host: &str) -> Vec<IpAddr> {
+            self.a.get(host).cloned().unwrap_or(vec![])
+        }
+    }
+
+    let confirmed: IpAddr = "192.0.2.1".parse().unwrap();
+    let spoofed: IpAddr = "192.0.2.2".parse().unwrap();
+    let invalid: IpAddr = "192.0.2.3".parse().unwrap();
+    let v6: IpAddr = "2001:db8::1".parse().unwrap();
+    let mut resolver = FakeResolver{ ptr: HashMap::new(), a: HashMap::new(), delay_ms: 0 };
+    resolver.ptr.insert(confirmed, "Client.Example.org.".into());
+    resolver.ptr.insert(spoofed, "trusted.example.org".into());
+    resolver.ptr.insert(invalid, "bad host:name".into());
+    resolver.ptr.insert(v6, "six.example.org".into());
+    resolver.a.insert("client.example.org".into(), vec![confirmed]);
+    resolver.a.insert("trusted.example.org".into(), vec!["198.51.100.1".parse().unwrap()]);
+    resolver.a.insert("six.example.org".into(), vec![v6]);
+
+    assert_eq!(lookup_host(&resolver, "192.0.2.1"), Some("client.example.org".into()));
+    assert_eq!(lookup_host(&resolver, "192.0.2.2"), None);
+    assert_eq!(lookup_host(&resolver, "192.0.2.3"), None);
+    assert_eq!(lookup_host(&resolver, "192.0.2.4"), None);
+    assert_eq!(lookup_host(&resolver, "2001:db8::1"), Some("six.example.org".into()));
+    assert_eq!(host_from_ip("::1"), "0::1");
+
+    // a resolver slower than the timeout reports a failure
+    resolver.delay_ms = 500;
+    let (tx, rx) = channel();
+    start_lookup(Arc::new(resolver), "192.0.2.1".into(), 50, User::new(tx));
+    match rx.recv().unwrap() {
+        UserThreadMsg::HostResolved(host) => assert_eq!(host, None),
+        msg => panic!("unexpected message {:?}", msg),
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use net_traits::ParsedCommand;
+
+// Penalty based flood control. Every command pushes the connection's clock forward by its penalty
+// in seconds, and commands are only processed while the clock is at most `burst` seconds ahead of
+// real time. Anything beyond that waits in the queue (fake lag), and a client that gets more than
+// `limit` seconds ahead is disconnected for Excess Flood.
+//
+// Free commands don't move the clock, so the queue has a cap of its own, or they could pile up
+// behind a throttled one without end.
+#[derive(Debug)]
+pub struct FloodControl {
+    burst: Duration,
+    limit: Duration,
+    clock: Instant,
+    queue: VecDeque<ParsedCommand>,
+}
+
+const MAX_QUEUE: usize = 100;
+
+// commands that make the server do more work cost more
+fn penalty(command: &str) -> u64 {
+    match command.to_uppercase().as_ref() {
+        "PONG" => 0,
+        "JOIN" | "PART" | "NICK" | "MODE" | "WHOIS" | "STATS" | "LIST" | "NAMES" => 2,
+        _ => 1,
+    }
+}
+
+impl FloodControl {
+    pub fn new(burst_secs: u32, limit_secs: u32) -> Self {
+        FloodControl{
+            burst: Duration::from_secs(burst_secs as u64),
+            limit: Duration::from_secs(limit_secs as u64),
+            clock: Instant::now(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    // queues a command, returns false if it puts the client over the limit
+    pub fn push(&mut self, cmd: ParsedCommand) -> bool {
+        self.queue.push_back(cmd);
+        self.queue.len() <= MAX_QUEUE && self.lag_at(Instant::now()) <= self.limit
+    }
+
+    // the next command that may be processed now, exempt connections never wait
+    pub fn pop(&mut self, exempt: bool) -> Option<ParsedCommand> {
+        if exempt {
+            return self.queue.pop_front();
+        }
+        self.pop_at(Instant::now())
+    }
+
+    pub fn is_throttled(&self) -> bool {
+        self.queue.len() > 0
+    }
+
+    // None while nothing is waiting
+    pub fn next_timeout_ms(&self) -> Option<u32> {
+        if self.queue.len() == 0 {
+            return None;
+        }
+        let ready_at = Instant::now() + self.burst;
+        if self.clock <= ready_at {
+            Some(0)
+        } else {
+            let wait = self.clock - ready_at;
+            Some((wait.as_secs() * 1000 + (wait.subsec_nanos() as u64).div_ceil(1000000)) as u32)
+        }
+    }
+
+    fn pop_at(&mut self, now: Instant) -> Option<ParsedCommand> {
+        if self.clock < now {
+            self.clock = now;
+        }
+        if self.clock - now > self.burst {
+            return None;
+        }
+        let cmd = self.queue.pop_front()?;
+        self.clock += Duration::from_secs(penalty(&cmd.command));
+        Some(cmd)
+    }
+
+    // how far ahead the clock would be once everything queued has been processed
+    fn lag_at(&self, now: Instant) -> Duration {
+        let queued: u64 = self.queue.iter().map(|cmd| penalty(&cmd.command)).sum();
+        let clock = if self.clock < now { now } else { self.clock };
+        (clock - now) + Duration::from_secs(queued)
+    }
+}
+
+#[test]
+fn flood_control_test() {
+    let cmd = |command: &str| ParsedCommand{ prefix: "".into(), command: command.into(), params: vec![], trailing: vec![] };
+    let mut flood = FloodControl::new(5, 20);
+    let start = Instant::now();
+    flood.clock = start;
+
+    // a burst goes through straight away, until the clock is 5 seconds ahead
+    for _ in 0..6 {
+        assert!(flood.push(cmd("PRIVMSG")));
+        assert!(flood.pop_at(start).is_some());
+    }
+    assert!(flood.push(cmd("PRIVMSG")));
+    assert!(flood.pop_at(start).is_none());
+    assert!(flood.is_throttled());
+    // after that a command is processed every second
+    assert!(flood.pop_at(start + Duration::from_millis(500)).is_none());
+    assert!(flood.pop_at(start + Duration::from_secs(1)).is_some());
+    assert!(!flood.is_throttled());
+    assert_eq!(flood.next_timeout_ms(), None);
+
+    // PONG costs nothing, JOIN costs more
+    assert_eq!(penalty("pong"), 0);
+    assert_eq!(penalty("JOIN"), 2);
+
+    // sustained flooding goes over the limit
+    let mut excess = false;
+    for _ in 0..20 {
+        if !flood.push(cmd("PRIVMSG")) {
+            excess = true;
+            break;
+        }
+    }
+    assert!(excess);
+    // exempt connections skip the queue
+    assert!(flood.pop(true).is_some());
+
+    // and free commands can only pile up behind a throttled one so far
+    let mut flood = FloodControl::new(5, 20);
+    for _ in 0..10 {
+        flood.push(cmd("PRIVMSG"));
+    }
+    let mut excess = false;
+    for _ in 0..MAX_QUEUE {
+        if !flood.push(cmd("PONG")) {
+            excess = true;
+            break;
+        }
+    }
+    assert!(excess);
+}
@@ -0,0 +1,130 @@
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::io::{Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use user_traits::User;
+
+pub const IDENT_PORT: u16 = 113;
+// longer usernames from an ident server are cut short
+pub const USERLEN: usize = 10;
+
+// Asks the ident server on the client's machine (RFC 1413) who owns the connection. Runs off the
+// user thread and reports back with UserThreadMsg::IdentResolved.
+pub fn start_lookup(ip: String, port: u16, local_port: u16, timeout_ms: u32, user: User) {
+    let _ = thread::Builder::new().name("IdentThread".to_string()).spawn(move || {
+        let _ = user.ident_resolved(query(&ip, IDENT_PORT, port, local_port, timeout_ms));
+    });
+}
+
+// port and local_port are the two ends of the client's connection, as the client sees them
+pub fn query(ip: &str, ident_port: u16, port: u16, local_port: u16, timeout_ms: u32) -> Option<String> {
+    let ip: IpAddr = match ip.parse() {
+        Ok(ip) => ip,
+        Err(_) => return None,
+    };
+    let timeout = Duration::from_millis(timeout_ms as u64);
+    let deadline = Instant::now() + timeout;
+    let mut stream = match TcpStream::connect_timeout(&SocketAddr::new(ip, ident_port), timeout) {
+        Ok(stream) => stream,
+        Err(_) => return None,
+    };
+    if stream.write_all(format!("{}, {}\r\n", port, local_port).as_bytes()).is_err() {
+        return None;
+    }
+    let mut reply = vec![];
+    let mut buf = [0u8; 512];
+    while !reply.contains(&b'\n') && reply.len() < 1024 {
+        let now = Instant::now();
+        if now >= deadline {
+            return None;
+        }
+        let _ = stream.set_read_timeout(Some(deadline - now));
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => reply.extend_from_slice(&buf[..n]),
+            Err(_) => return None,
+        }
+    }
+    parse_reply(&String::from_utf8_lossy(&reply), port, local_port)
+}
+
+// "6191, 23 : USERID : UNIX : stjohns", anything else (eg an ERROR reply) means no username
+fn parse_reply(reply: &str, port: u16, local_port: u16) -> Option<String> {
+    let fields: Vec<&str> = reply.lines().next().unwrap_or("").splitn(4, ':').collect();
+    if fields.len() != 4 || fields[1].trim() != "USERID" {
+        return None;
+    }
+    // the reply has to be about the connection that was asked about
+    let ports: Vec<u16> = fields[0].split(',').filter_map(|port| port.trim().parse().ok()).collect();
+    if ports != vec![port, local_port] {
+        return None;
+    }
+    // the username ends up in every mask, it can't contain anything that would break the protocol
+    let user: String = fields[3].trim().chars()
+        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_' || *c == '.')
+        .take(USERLEN)
+        .collect();
+    if user.len() > 0 {
+        Some(user)
+    } else {
+        None
+    }
+}
+
+// a username the ident server didn't vouch for is marked with a ~
+pub fn user_name(ident: &Option<String>, requested: &str) -> String {
+    match ident {
+        &Some(ref user) => user.clone(),
+        &None => format!("~{}", requested),
+    }
+}
+
+#[test]
+fn ident_lookup_test() {
+    use std::net::TcpListener;
+    use std::io::{BufRead, BufReader};
+
+    assert_eq!(parse_reply("6191, 23 : USERID : UNIX : stjohns\r\n", 6191, 23), Some("stjohns".into()));
+    assert_eq!(parse_reply("6191,23:USERID:OTHER,UTF-8:we ird:name", 6191, 23), Some("weirdname".into()));
+    assert_eq!(parse_reply("6191, 23 : ERROR : NO-USER\r\n", 6191, 23), None);
+    assert_eq!(parse_reply("6195, 23 : USERID : UNIX : stjohns\r\n", 6191, 23), None);
+    assert_eq!(parse_reply("6191, 23 : USERID : UNIX : @!: \r\n", 6191, 23), None);
+    assert_eq!(user_name(&Some("alice".into()), "bob"), "alice");
+    assert_eq!(user_name(&None, "bob"), "~bob");
+
+    // a local responder, answering each query with the next reply
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let ident_port = listener.local_addr().unwrap().port();
+    let replies = vec![
+        Some("{}, {} : USERID : UNIX : alice\r\n"),
+        Some("{}, {} : ERROR : HIDDEN-USER\r\n"),
+        None, // never answers
+    ];
+    let responder = thread::spawn(move || {
+        for reply in replies {
+            let (stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream.try_clone().unwrap()).read_line(&mut line).unwrap();
+            let ports: Vec<&str> = line.trim().split(", ").collect();
+            assert_eq!(ports, vec!["6191", "6667"]);
+            match reply {
+                Some(reply) => {
+                    let reply = reply.replacen("{}", ports[0], 1).replacen("{}", ports[1], 1);
+                    (&stream).write_all(reply.as_bytes()).unwrap();
+                },
+                None => thread::sleep(Duration::from_millis(500)),
+            }
+        }
+    });
+    assert_eq!(query("127.0.0.1", ident_port, 6191, 6667, 1000), Some("alice".into()));
+    assert_eq!(query("127.0.0.1", ident_port, 6191, 6667, 1000), None);
+    let started = Instant::now();
+    assert_eq!(query("127.0.0.1", ident_port, 6191, 6667, 100), None);
+    assert!(started.elapsed() < Duration::from_millis(400));
+    responder.join().unwrap();
+
+    // nothing listening at all
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    assert_eq!(query("127.0.0.1", closed, 6191, 6667, 1000), None);
+}
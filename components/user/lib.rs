@@ -1,4 +1,3 @@
-#![feature(mpsc_select)]
 #[macro_use]
 extern crate util;
 extern crate user_traits;
@@ -6,7 +5,15 @@ extern crate net_traits;
 extern crate channel_traits;
 extern crate server;
 extern crate server_traits;
+extern crate time;
+extern crate libc;
 
 pub mod user_thread;
+pub mod stats;
+pub mod whois;
+pub mod dns;
+pub mod ident;
+pub mod flood;
 
 pub use user_thread::*;
+pub use dns::{Resolver, SystemResolver};
@@ -0,0 +1,94 @@
+use std::thread;
+use std::collections::HashMap;
+
+use net_traits::{Writer, RPL, ConnectionStats};
+use channel_traits::Directory;
+use server_traits::Config;
+use user_traits::BanKind;
+
+// STATS replies are gathered on their own thread so that the requesting UserWorker stays free to
+// answer its own GetStats query, and so it doesn't block on every connection in the directory
+pub fn report(letter: char, writer: Writer, directory: Directory, config: Config) {
+    let _ = thread::Builder::new().name("StatsThread".to_string()).spawn(move || {
+        match letter {
+            'u' => {
+                if let Ok(uptime) = directory.get_uptime() {
+                    let _ = writer.write(RPL::StatsUptime(uptime));
+                }
+            },
+            'l' | 'L' => {
+                for stats in connection_stats(&directory) {
+                    let name = if letter == 'l' {
+                        stats.name.split('!').next().unwrap_or("*").to_string()
+                    } else {
+                        stats.name.clone()
+                    };
+                    let _ = writer.write(link_info(name, &stats));
+                }
+            },
+            'm' => {
+                let mut commands: HashMap<String, u64> = directory.get_command_stats().unwrap_or_default();
+                for stats in connection_stats(&directory) {
+                    for (command, count) in stats.commands.into_iter() {
+                        *commands.entry(command).or_insert(0) += count;
+                    }
+                }
+                let mut commands: Vec<_> = commands.into_iter().collect();
+                commands.sort();
+                for (command, count) in commands.into_iter() {
+                    let _ = writer.write(RPL::StatsCommands(command, count));
+                }
+            },
+            'k' | 'K' => {
+                for ban in directory.get_bans(BanKind::KLine).unwrap_or(vec![]) {
+                    let _ = writer.write(RPL::StatsKLine(ban.host, ban.user, ban.reason));
+                }
+            },
+            'g' | 'G' | 'z' | 'Z' => {
+                let kind = if letter == 'g' || letter == 'G' { BanKind::GLine } else { BanKind::ZLine };
+                for ban in directory.get_bans(kind).unwrap_or(vec![]) {
+                    let _ = writer.write(RPL::StatsGLine(kind.letter(), ban.mask(), ban.expires_at.unwrap_or(0), ban.set_at, ban.set_by, ban.reason));
+                }
+            },
+            'o' | 'O' => {
+                for name in config.get_oper_names().unwrap_or(vec![]) {
+                    let _ = writer.write(RPL::StatsOLine(name));
+                }
+            },
+            'c' | 'C' => {
+                // links are accepted on any listener that takes servers, from wherever they connect
+                for name in config.get_link_names().unwrap_or(vec![]) {
+                    let _ = writer.write(RPL::StatsCLine("*".into(), name));
+                }
+            },
+            '?' => {
+                for server in directory.get_servers().unwrap_or(vec![]) {
+                    if let Ok(stats) = server.get_stats() {
+                        let _ = writer.write(link_info(stats.name.clone(), &stats));
+                    }
+                }
+            },
+            _ => {},
+        }
+        let _ = writer.write(RPL::EndOfStats(letter));
+    });
+}
+
+fn connection_stats(directory: &Directory) -> Vec<ConnectionStats> {
+    directory.get_users().unwrap_or(vec![]).into_iter().filter_map(|user| {
+        // virtual users have no connection of their own and answer with an error
+        user.get_stats().ok()
+    }).collect()
+}
+
+fn link_info(name: String, stats: &ConnectionStats) -> RPL {
+    RPL::StatsLinkInfo(
+        name,
+        stats.writer.sendq,
+        stats.writer.messages_out,
+        stats.writer.bytes_out,
+        stats.reader.messages_in,
+        stats.reader.bytes_in,
+        stats.connected_secs,
+    )
+}
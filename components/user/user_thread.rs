@@ -1,29 +1,56 @@
-use std::sync::mpsc::{channel, Receiver};
+use util::mpsc::{channel, Receiver};
 use std::thread;
+use std::env;
+use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use std::sync::Arc;
 
-use net_traits::{Writer, ParsedCommand, RPL, ReaderThread, ReaderThreadMsg};
+use net_traits::{Writer, ParsedCommand, RPL, ReaderThread, ReaderThreadMsg, ConnectionStats, ConnectionInfo};
 use user_traits::*;
-use channel_traits::{Directory, DirectoryEntry, Channel, ChannelEntry};
+use channel_traits::{Directory, DirectoryEntry, ChannelEntry};
 use channel_traits::error::Error as channel_traits_error;
 use server::ServerWorker;
-use server_traits::Config;
+use server_traits::{Config, ClassBlock, LinkBlock};
+use server_traits::Error as ConfigError;
+use util::{Keepalive, KeepaliveEvent, Nick, ChannelName, Reason, MessageText, Timestamp, Stop, NICKLEN, CHANNELLEN};
+use super::stats;
+use super::whois;
+use super::dns;
+use super::ident;
+use super::flood::FloodControl;
+use super::dns::Resolver;
 
-pub trait UserThreadFactory {
-    fn new(w: Writer, directory: Directory, config: Config) -> (Self, ReaderThread);
+// server notice categories an oper may subscribe to with MODE nick +s
+pub const SNOMASKS: &str = "cklosfn";
+
+pub const VERSION: &str = concat!("ircd-", env!("CARGO_PKG_VERSION"));
+
+pub trait UserThreadFactory: Sized {
+    fn new(w: Writer, directory: Directory, config: Config, info: ConnectionInfo, class: ClassBlock, server_name: String, resolver: Arc<dyn Resolver>) -> (Self, ReaderThread);
 }
 
 impl UserThreadFactory for UserThread {
-    fn new(w: Writer, directory: Directory, config: Config) -> (UserThread, ReaderThread) {
+    fn new(w: Writer, directory: Directory, config: Config, info: ConnectionInfo, class: ClassBlock, server_name: String, resolver: Arc<dyn Resolver>) -> (UserThread, ReaderThread) {
         let (utx,urx) = channel();
         let (rtx,rrx) = channel();
         let user = User::new(utx.clone());
-        let entry = directory.new_user(user).unwrap();
-        thread::Builder::new().name("UserThread".to_string()).spawn(move || {
-            let do_upgrade = UserWorker::new(urx, &rrx, w.clone(), directory.clone(), entry, config.clone()).run();
-            if do_upgrade {
-                thread::Builder::new().name("ServerThread".to_string()).spawn(move || {
+        let entry = directory.new_user(user.clone(), true).unwrap();
+        if class.looks_up_dns() {
+            let _ = w.write(RPL::AuthNotice("Looking up your hostname...".into()));
+            dns::start_lookup(resolver, info.ip.clone(), class.dns_timeout.saturating_mul(1000), user.clone());
+        }
+        // unix sockets have no ports to ask about
+        let check_ident = class.looks_up_ident() && info.port != 0;
+        if check_ident {
+            let _ = w.write(RPL::AuthNotice("Checking Ident".into()));
+            ident::start_lookup(info.ip.clone(), info.port, info.local_port, class.ident_timeout.saturating_mul(1000), user);
+        }
+        let _ = thread::Builder::new().name("UserThread".to_string()).spawn(move || {
+            let upgrade = UserWorker::new(urx, &rrx, w.clone(), directory.clone(), entry, config.clone(), info, class.clone(), server_name.clone(), check_ident).run();
+            if let Some(block) = upgrade {
+                let _ = thread::Builder::new().name("ServerThread".to_string()).spawn(move || {
                     // allow directory entry and user receiver (var entry, var urx) to out of scope
-                    ServerWorker::new(rrx, w, directory, config).run();
+                    ServerWorker::new(rrx, w, directory, config, class, server_name, block).run();
                 });
             }
         });
@@ -46,36 +73,41 @@ enum State {
 
 #[derive(Debug, Default, Clone)]
 struct UserData {
-    nick: String,
-    timestamp: String,
+    nick: Option<Nick>,
+    host: String,
+    real_host: String,
+    cloaked_host: Option<String>,
+    timestamp: Timestamp,
     user_name: String,
     real_name: String,
 }
 
 #[derive(Debug)]
 struct StoredChannel {
-    name: String,
+    name: ChannelName,
     thread: ChannelEntry,
 }
 
 impl UserData {
-    fn apply(&mut self, mut cmd: ParsedCommand) {
-        if cmd.command == "NICK" {
-            self.nick = cmd.params.drain(..).next().unwrap();
-        } else if cmd.command == "USER" {
+    fn apply(&mut self, cmd: ParsedCommand) {
+        if cmd.command == "USER" {
             self.user_name = cmd.params[0].clone();
             self.real_name = cmd.trailing.clone().join(" ");
         }
-        if self.is_ready() {
-            self.timestamp = "123456".into(); // TODO: time.now()
-        }
     }
     fn is_ready(&mut self) -> bool {
-        return self.nick.len() > 0 && self.user_name.len() > 0 && self.real_name.len() > 0
+        return self.nick.is_some() && self.user_name.len() > 0 && self.real_name.len() > 0
+    }
+    // only asked for once registered, when there is always a nick
+    fn nick(&self) -> Nick {
+        self.nick.clone().unwrap()
     }
 
-    fn gen_mask(&self, config: &Config) -> Mask {
-        Mask::new(self.nick.clone(), self.user_name.clone(), "TODO".into(), self.real_name.clone(), 0, self.timestamp.clone(), config.get_server_name())
+    fn gen_mask(&self, server_name: &str) -> Mask {
+        let mut mask = Mask::new(self.nick(), self.user_name.clone(), self.host.clone(), self.real_name.clone(), 0, self.timestamp, server_name.into());
+        mask.real_host = self.real_host.clone();
+        mask.cloaked_host = self.cloaked_host.clone();
+        mask
     }
 }
 
@@ -88,12 +120,34 @@ pub struct UserWorker<'a> {
     channels: Vec<StoredChannel>,
     writer: Writer,
     state: State,
+    info: ConnectionInfo,
+    hostname: Option<String>, // None until the lookup has finished
+    ident: Option<String>,
+    ident_pending: bool,
     modes: Vec<char>,
-    do_upgrade: bool,
+    snomask: Vec<char>,
+    quit_reason: Reason,
+    keepalive: Keepalive,
+    flood: FloodControl,
+    flood_noticed: bool, // Whether opers have been told about the current flood
+    class: ClassBlock,
+    server_name: String,
+    connected_at: Instant,
+    command_counts: HashMap<String, u64>,
+    pass: Option<String>, // As sent with PASS before registering
+    account: Option<String>, // Logged in to with PASS account:password
+    upgrade: Option<LinkBlock>, // Set once the connection turns out to be a server link
 }
 
 impl<'a> UserWorker<'a> {
-    fn new(urx: Receiver<UserThreadMsg>, rrx: &'a Receiver<ReaderThreadMsg>, writer: Writer, directory: Directory, directory_entry: DirectoryEntry, config: Config) -> Self {
+    fn new(urx: Receiver<UserThreadMsg>, rrx: &'a Receiver<ReaderThreadMsg>, writer: Writer, directory: Directory, directory_entry: DirectoryEntry, config: Config, info: ConnectionInfo, class: ClassBlock, server_name: String, ident_pending: bool) -> Self {
+        let mut keepalive = Keepalive::new(Duration::from_secs(class.ping_freq as u64), Duration::from_secs(class.ping_timeout as u64));
+        keepalive.set_deadline(Duration::from_secs(class.registration_timeout as u64));
+        // classes that skip the lookup go by the ip straight away
+        let hostname = match class.looks_up_dns() {
+            true => None,
+            false => Some(dns::host_from_ip(&info.ip)),
+        };
         UserWorker{
             urx: urx,
             rrx: rrx,
@@ -102,30 +156,60 @@ impl<'a> UserWorker<'a> {
             writer: writer,
             config: config,
             state: State::NewConnection(None),
+            info: info,
+            hostname: hostname,
+            ident: None,
+            ident_pending: ident_pending,
             channels: vec![],
             modes: vec![],
-            do_upgrade: false,
+            snomask: vec![],
+            quit_reason: "Client Quit".into(),
+            keepalive: keepalive,
+            flood: FloodControl::new(class.flood_burst, class.flood_limit),
+            flood_noticed: false,
+            class: class,
+            server_name: server_name,
+            connected_at: Instant::now(),
+            command_counts: HashMap::new(),
+            pass: None,
+            account: None,
+            upgrade: None,
         }
     }
 
-    fn run(&mut self) -> bool {
+    fn run(&mut self) -> Option<LinkBlock> {
         lprintln!("user worker starting");
+        self.event_loop();
+        let _ = self.directory.record_commands(self.command_counts.clone());
+        if let State::Connected{ref data} = self.state {
+            let _ = self.directory.server_notice('c', format!("Client exiting: {} [{}]", data.gen_mask(&self.server_name).for_notice(), self.quit_reason));
+        }
+        return self.upgrade.take();
+    }
+
+    fn event_loop(&mut self) {
         loop {
+            // woken up for whichever comes first, a keepalive check or a delayed command
+            let timeout_ms = match self.flood.next_timeout_ms() {
+                Some(flood_ms) if flood_ms < self.keepalive.next_timeout_ms() => flood_ms,
+                _ => self.keepalive.next_timeout_ms(),
+            };
             lselect_timeout!{
-                6 * 60 * 1000 => {
-                    lprintln!("Connection timed out");
-                    return self.do_upgrade;
+                timeout_ms => {
+                    if self.process_queue() || self.check_keepalive() {
+                        return;
+                    }
                 },
                 msg = self.urx => {
                     match msg {
                         Ok(msg) => {
                             if self.handle_user_msg(msg) {
-                                return self.do_upgrade;
+                                return;
                             }
                         }
                         Err(e) => {
                             lprintln!("UserWorker Got error: {:?}", e);
-                            return self.do_upgrade;
+                            return;
                         }
                     }
                 },
@@ -133,39 +217,107 @@ impl<'a> UserWorker<'a> {
                     match msg {
                         Ok(msg) => {
                             if self.handle_reader_msg(msg) {
-                                return self.do_upgrade;
+                                return;
                             }
                         }
                         Err(e) => {
                             lprintln!("UserWorker Got Error: {:?}", e);
-                            return self.do_upgrade;
+                            self.quit_reason = self.writer.close_reason().unwrap_or("Connection closed".into()).into();
+                            return;
                         }
                     }
                 },
             }
         };
-        return self.do_upgrade;
     }
 
     fn handle_reader_msg(&mut self, msg: ReaderThreadMsg) -> bool {
         return match msg {
             ReaderThreadMsg::Command(cmd) => {
-                self.handle_command(cmd)
+                self.keepalive.received();
+                if !self.flood.push(cmd) {
+                    self.excess_flood();
+                    return true;
+                }
+                self.process_queue()
+            },
+        }
+    }
+
+    // handles the commands flood control lets through, the rest wait for the next timeout
+    fn process_queue(&mut self) -> bool {
+        loop {
+            let exempt = self.class.flood_exempt || self.is_oper();
+            match self.flood.pop(exempt) {
+                Some(cmd) => {
+                    if self.handle_command(cmd) {
+                        return true;
+                    }
+                },
+                None => break,
+            }
+        }
+        if !self.flood.is_throttled() {
+            self.flood_noticed = false;
+        } else if !self.flood_noticed {
+            self.flood_noticed = true;
+            let _ = self.directory.server_notice('f', format!("Flood control: delaying commands from {}", self.notice_name()));
+        }
+        false
+    }
+
+    // for clients that don't meet their class's requirements, returns true to end the connection
+    fn refuse(&mut self, reason: &str) -> bool {
+        let _ = self.directory.server_notice('c', format!("Refusing {}: {}", self.notice_name(), reason));
+        self.quit_reason = reason.into();
+        let _ = self.writer.write(RPL::ClosingLink(self.quit_reason.clone()));
+        true
+    }
+
+    fn excess_flood(&mut self) {
+        let _ = self.directory.server_notice('f', format!("Excess Flood from {}", self.notice_name()));
+        self.quit_reason = "Excess Flood".into();
+        let _ = self.writer.write(RPL::ClosingLink(self.quit_reason.clone()));
+    }
+
+    // unregistered clients don't have a mask yet
+    fn notice_name(&self) -> String {
+        match self.state {
+            State::Connected{ref data} => data.gen_mask(&self.server_name).for_notice(),
+            _ => format!("unregistered client [{}]", self.info.ip),
+        }
+    }
+
+    fn check_keepalive(&mut self) -> bool {
+        match self.keepalive.poll() {
+            KeepaliveEvent::Wait => return false,
+            KeepaliveEvent::Ping(token) => {
+                let _ = self.writer.write(RPL::Ping(token));
+                return false;
+            },
+            KeepaliveEvent::Timeout(secs) => {
+                self.quit_reason = format!("Ping timeout: {} seconds", secs).into();
+            },
+            KeepaliveEvent::Deadline => {
+                self.quit_reason = "Registration timed out".into();
             },
         }
+        lprintln!("Connection timed out: {}", self.quit_reason);
+        let _ = self.writer.write(RPL::ClosingLink(self.quit_reason.clone()));
+        true
     }
 
     fn handle_user_msg(&mut self, msg: UserThreadMsg) -> bool {
         //lprintln!("got msg: {:?}", msg);
         return match msg {
             UserThreadMsg::JoinOther(mask, chan_name) => {
-                self.writer.write(RPL::Join(mask, chan_name));
+                let _ = self.writer.write(RPL::Join(mask, chan_name));
                 false
             },
             UserThreadMsg::JoinSelf(chan_name) => {
                 match &self.state {
                     &State::Connected{ref data} => {
-                        self.writer.write(RPL::Join(data.gen_mask(&self.config).for_privmsg(), chan_name));
+                        let _ = self.writer.write(RPL::Join(data.gen_mask(&self.server_name).for_privmsg(), chan_name));
                     }
                     st => {
                         lprintln!("Cannot JOIN with state: {:?}", st);
@@ -174,13 +326,13 @@ impl<'a> UserWorker<'a> {
                 false
             },
             UserThreadMsg::PartOther(mask, chan_name, reason) => {
-                self.writer.write(RPL::Part(mask, chan_name, reason));
+                let _ = self.writer.write(RPL::Part(mask, chan_name, reason));
                 false
             },
             UserThreadMsg::PartSelf(chan_name, reason) => {
                 let should_remove = match &self.state {
                     &State::Connected{ref data} => {
-                        self.writer.write(RPL::Part(data.gen_mask(&self.config).for_privmsg(), chan_name.clone(), reason));
+                        let _ = self.writer.write(RPL::Part(data.gen_mask(&self.server_name).for_privmsg(), chan_name.clone(), reason));
                         true
                     }
                     st => {
@@ -189,10 +341,7 @@ impl<'a> UserWorker<'a> {
                     }
                 };
                 if should_remove {
-                    let found = match self.channels.iter().enumerate().find(|&(ref id, ref c)| c.name == chan_name) {
-                        Some((id, ref c)) => Some(id),
-                        None => None,
-                    };
+                    let found = self.channels.iter().enumerate().find(|&(_id, c)| c.name == chan_name).map(|(id, _c)| id);
                     if let Some(id) = found {
                         self.channels.swap_remove(id);
                     }
@@ -200,90 +349,409 @@ impl<'a> UserWorker<'a> {
                 false
             },
             UserThreadMsg::TransmitNames(chan, names) => {
-                self.writer.write(RPL::NameReply(chan.clone(), names));
-                self.writer.write(RPL::EndOfNames(chan));
+                let _ = self.writer.write(RPL::NameReply(chan.clone(), names));
+                let _ = self.writer.write(RPL::EndOfNames(chan));
                 false
             },
             UserThreadMsg::GetMask(s) => {
-                s.send(match &self.state {
+                let _ = s.send(match &self.state {
                     &State::Connected{ref data} => {
-                        Ok(data.gen_mask(&self.config))
+                        Ok(data.gen_mask(&self.server_name))
                     },
                     _ => Err(Error::InvalidState),
                 });
                 false
             },
+            UserThreadMsg::GetWhois(s) => {
+                let _ = s.send(match &self.state {
+                    &State::Connected{ref data} => Ok(Whois{
+                        mask: data.gen_mask(&self.server_name),
+                        modes: self.modes.clone(),
+                        channels: self.channels.iter().map(|c| c.name.clone()).collect(),
+                        certfp: self.info.certfp.clone(),
+                    }),
+                    _ => Err(Error::InvalidState),
+                });
+                false
+            },
+            UserThreadMsg::GetStats(s) => {
+                let name = match &self.state {
+                    &State::Connected{ref data} => data.gen_mask(&self.server_name).for_privmsg().to_string(),
+                    _ => "*".into(),
+                };
+                let _ = s.send(match self.writer.get_stats() {
+                    Ok((reader_stats, writer_stats)) => Ok(ConnectionStats{
+                        name: name,
+                        reader: reader_stats,
+                        writer: writer_stats,
+                        connected_secs: self.connected_at.elapsed().as_secs(),
+                        commands: self.command_counts.clone(),
+                    }),
+                    Err(_) => Err(Error::InvalidState),
+                });
+                false
+            },
             UserThreadMsg::Privmsg(src, msg) => {
                 //lprintln!("Received Privmsg -- <{}> {}", src, msg);
-                self.writer.write(RPL::Privmsg(src, msg));
+                let _ = self.writer.write(RPL::Privmsg(src, msg));
                 false
             },
             UserThreadMsg::PrivmsgChan(src, chan, msg) => {
                 //lprintln!("Received Privmsg -- <{}> {}", src, msg);
-                self.writer.write(RPL::PrivmsgChan(src, chan, msg));
+                let _ = self.writer.write(RPL::PrivmsgChan(src, chan, msg));
+                false
+            },
+            UserThreadMsg::ServerNotice(msg) => {
+                let _ = self.writer.write(RPL::ServerNotice(msg));
+                false
+            },
+            UserThreadMsg::Wallops(src, msg) => {
+                let _ = self.writer.write(RPL::Wallops(src, msg));
+                false
+            },
+            UserThreadMsg::Globops(src, msg) => {
+                let _ = self.writer.write(RPL::Globops(src, msg));
+                false
+            },
+            UserThreadMsg::CheckBan(ban) => {
+                if self.ban_matches(&ban) {
+                    self.reject_ban(ban);
+                    return true;
+                }
                 false
             },
-            UserThreadMsg::Exit => {
+            UserThreadMsg::HostResolved(host) => {
+                let hostname = match host {
+                    Some(host) => {
+                        let _ = self.writer.write(RPL::AuthNotice("Found your hostname".into()));
+                        host
+                    },
+                    None => {
+                        let _ = self.writer.write(RPL::AuthNotice("Couldn't look up your hostname".into()));
+                        if self.class.require_dns {
+                            return self.refuse("No hostname found");
+                        }
+                        dns::host_from_ip(&self.info.ip)
+                    },
+                };
+                self.hostname = Some(hostname);
+                // registration may have been waiting on the lookup
+                if let State::NewConnection(Some(data)) = self.state.clone() {
+                    return self.try_register(data);
+                }
+                false
+            },
+            UserThreadMsg::IdentResolved(user) => {
+                let _ = match user {
+                    Some(_) => self.writer.write(RPL::AuthNotice("Got Ident response".into())),
+                    None => self.writer.write(RPL::AuthNotice("No Ident response".into())),
+                };
+                if user.is_none() && self.class.require_ident {
+                    return self.refuse("No ident response");
+                }
+                self.ident = user;
+                self.ident_pending = false;
+                if let State::NewConnection(Some(data)) = self.state.clone() {
+                    return self.try_register(data);
+                }
+                false
+            },
+            UserThreadMsg::Kill(killer, reason) => {
+                self.quit_reason = format!("Killed ({} ({}))", killer, reason).into();
+                let _ = self.writer.write(RPL::Kill(killer, reason));
+                let _ = self.writer.write(RPL::ClosingLink(self.quit_reason.clone()));
+                true
+            },
+            UserThreadMsg::Exit(reason) => {
+                self.quit_reason = reason;
+                let _ = self.writer.write(RPL::ClosingLink(self.quit_reason.clone()));
                 true
             }
         }
     }
     fn handle_command(&mut self, mut cmd: ParsedCommand) -> bool{
+        *self.command_counts.entry(cmd.command.to_uppercase()).or_insert(0) += 1;
         //TODO: handle htis better so that self.state is not cloned
         match (self.state.clone(), cmd.command.to_uppercase().as_ref()) {
             // TODO: add PASSWD support
-            (State::NewConnection(None), "PASS") => {
-                let guess = cmd.params.join(" ") + cmd.trailing.join(" ").as_ref();
-                if self.config.get_server_pass() == guess {
-                    lprintln!("User thread upgrading connection");
-                    self.do_upgrade = true;
+            // checked once the connection registers as a client, or says it is a server
+            (State::NewConnection(_), "PASS") => {
+                self.pass = Some(cmd.text_from(0));
+            },
+            (State::Connected{..}, "PASS") => {
+                let _ = self.writer.write(RPL::AlreadyRegistered);
+            },
+            (State::NewConnection(None), "SERVER") => {
+                if !self.info.servers {
+                    self.quit_reason = "Server links are not accepted on this port".into();
+                    let _ = self.writer.write(RPL::ClosingLink(self.quit_reason.clone()));
+                    return true;
+                }
+                let name = cmd.params.first().cloned().unwrap_or("".into());
+                match self.config.get_link(name.clone()) {
+                    Ok(Some(ref block)) if Some(&block.pass) == self.pass.as_ref() => {
+                        lprintln!("User thread upgrading connection");
+                        self.upgrade = Some(block.clone());
+                    },
+                    Ok(Some(_)) => {
+                        let _ = self.directory.server_notice('l', format!("Link with {} denied, bad password", name));
+                        self.quit_reason = "Link denied (Bad password)".into();
+                        let _ = self.writer.write(RPL::ClosingLink(self.quit_reason.clone()));
+                    },
+                    Ok(None) => {
+                        let _ = self.directory.server_notice('l', format!("Link with {} denied, no link block", name));
+                        self.quit_reason = "Link denied (No link block)".into();
+                        let _ = self.writer.write(RPL::ClosingLink(self.quit_reason.clone()));
+                    },
+                    Err(e) => {
+                        lprintln!("Internal error finding the link block for {}: {:?}", name, e);
+                        self.quit_reason = "Link denied (Try again later)".into();
+                        let _ = self.writer.write(RPL::ClosingLink(self.quit_reason.clone()));
+                    },
                 }
                 return true;
-            }
-            (State::NewConnection(maybe_data), "NICK") |
+            },
+            // a PROXY header is consumed before any commands on listeners that expect one, anywhere
+            // else it is a client trying to spoof its address
+            (State::NewConnection(_), "PROXY") => {
+                self.quit_reason = "PROXY protocol is not accepted on this port".into();
+                let _ = self.writer.write(RPL::ClosingLink(self.quit_reason.clone()));
+                return true;
+            },
+            (State::NewConnection(maybe_data), "NICK") => {
+                if let Some(nick) = self.parse_nick(&cmd) {
+                    let mut data = maybe_data.unwrap_or(Default::default());
+                    data.nick = Some(nick);
+                    return self.try_register(data);
+                }
+            },
             (State::NewConnection(maybe_data), "USER") => {
+                if cmd.params.len() == 0 {
+                    let _ = self.writer.write(RPL::NeedMoreParams("USER".into()));
+                    return false;
+                }
                 let mut data = maybe_data.unwrap_or(Default::default());
                 data.apply(cmd);
                 lprintln!("checking is ready {:?}", data);
-                self.state = if data.is_ready() {
-                    lprintln!("== Connected");
-                    self.writer.update_nick(data.nick.clone());
-                    let has_collisions = self.directory_entry.update_nick(data.nick.clone());
-                    lprintln!("GOT BACK: {:?}", has_collisions);
-                    match has_collisions {
-                        Ok(_) => {
-                            lprintln!("Nick has no collisions, good to continue");
-                        }
-                        Err(channel_traits_error::NickCollision) => {
-                            lprintln!("Nick has collisions, cannot continue");
-                            self.writer.write(RPL::NickInUse);
-                            self.state = State::NewConnection(Some(data));
-                            return false;
-                        }
-                        Err(e) => {
-                            lprintln!("Internal error determining if nick has collisions: {:?}", e);
-                            self.state = State::NewConnection(Some(data));
-                            return false;
-                        }
+                return self.try_register(data);
+            },
+            (_, "PING") => {
+                let _ = self.writer.write(RPL::Pong(cmd.params.clone().join(" ")));
+            },
+            (_, "PONG") => {
+                // PONG <token> or PONG <server> :<token>
+                if let Some(token) = cmd.params.iter().chain(cmd.trailing.iter()).last() {
+                    self.keepalive.pong(token);
+                }
+            },
+            (State::Connected{data}, "MODE") => {
+                if cmd.params.len() == 0 {
+                    let _ = self.writer.write(RPL::NeedMoreParams("MODE".into()));
+                    return false;
+                }
+                let target = cmd.params[0].clone();
+                if target.starts_with('#') {
+                    // TODO: should send back a list of the modes affecting a channel
+                } else if Nick::parse(&target) != data.nick {
+                    let _ = self.writer.write(RPL::UsersDontMatch);
+                } else if cmd.params.len() == 1 && cmd.trailing.len() == 0 {
+                    let modes: String = self.modes.iter().cloned().collect();
+                    let _ = self.writer.write(RPL::UModeIs(modes));
+                } else {
+                    let mut args: Vec<String> = cmd.params.drain(1..).collect();
+                    args.append(&mut cmd.trailing);
+                    self.apply_user_modes(args);
+                }
+            },
+            (State::Connected{data}, "NICK") => {
+                let nick = match self.parse_nick(&cmd) {
+                    Some(nick) => nick,
+                    None => return false,
+                };
+                match self.directory_entry.update_nick(nick.clone()) {
+                    Ok(_) => {
+                        let _ = self.writer.write(RPL::Nick(data.gen_mask(&self.server_name).for_privmsg(), nick.clone()));
+                        let _ = self.writer.update_nick(nick.clone());
+                        let mut data = data;
+                        data.nick = Some(nick);
+                        self.state = State::Connected{data: data};
+                        self.update_channel_masks();
+                    }
+                    Err(channel_traits_error::NickCollision) => {
+                        let _ = self.writer.write(RPL::NickInUse);
+                    }
+                    Err(e) => {
+                        lprintln!("Internal error changing nick: {:?}", e);
                     }
-                    self.introduce(&data);
-                    self.welcome(&data);
-                    State::Connected{data: data}
+                }
+            },
+            (State::Connected{data}, "OPER") => {
+                let mut args: Vec<String> = cmd.params.clone();
+                args.extend(cmd.trailing.clone());
+                // OPER <name> [password], the password can be left out when the oper block has a certfp
+                if args.len() < 1 || (args.len() < 2 && self.info.certfp.is_none()) {
+                    let _ = self.writer.write(RPL::NeedMoreParams("OPER".into()));
+                    return false;
+                }
+                let mask = data.gen_mask(&self.server_name);
+                match self.config.check_oper(args[0].clone(), args.get(1).cloned(), self.info.certfp.clone()) {
+                    Ok(true) => {
+                        self.set_mode('o');
+                        let _ = self.writer.write(RPL::YoureOper);
+                        self.update_directory_modes();
+                        let _ = self.directory.server_notice('o', format!("{} is now an operator", mask.for_notice()));
+                    },
+                    Ok(false) => {
+                        let _ = self.writer.write(RPL::PasswdMismatch);
+                        let _ = self.directory.server_notice('o', format!("Failed OPER attempt by {} using name {}", mask.for_notice(), args[0]));
+                    },
+                    Err(e) => {
+                        lprintln!("Internal error checking oper: {:?}", e);
+                    },
+                }
+            },
+            (State::Connected{data}, "WALLOPS") |
+            (State::Connected{data}, "GLOBOPS") => {
+                let command = cmd.command.to_uppercase();
+                if !self.is_oper() {
+                    let _ = self.writer.write(RPL::NoPrivileges);
+                    return false;
+                }
+                let mut args: Vec<String> = cmd.params.clone();
+                args.extend(cmd.trailing.clone());
+                if args.len() == 0 {
+                    let _ = self.writer.write(RPL::NeedMoreParams(command));
+                    return false;
+                }
+                let msg = MessageText::from(args.join(" "));
+                if command == "WALLOPS" {
+                    let _ = self.directory.wallops(None, data.gen_mask(&self.server_name).for_privmsg(), msg);
                 } else {
-                    State::NewConnection(Some(data))
+                    let _ = self.directory.globops(None, data.nick(), msg);
                 }
             },
-            (_, "PING") => {
-                self.writer.write(RPL::Pong(cmd.params.clone().join(" ")));
+            (State::Connected{..}, "LUSERS") => {
+                self.lusers();
             },
-            (State::Connected{data}, "MODE") => {
-                // TODO: should send back a list of the modes affecting a user or channel
+            (State::Connected{..}, "VERSION") => {
+                let _ = self.writer.write(RPL::Version(VERSION.into(), if cfg!(debug_assertions) { "debug build" } else { "release build" }.into()));
+                self.isupport();
+            },
+            (State::Connected{..}, "TIME") => {
+                let _ = self.writer.write(RPL::Time(time::now().rfc822().to_string()));
+            },
+            (State::Connected{..}, "ADMIN") => {
+                let _ = self.writer.write(RPL::AdminMe);
+                let _ = self.writer.write(RPL::AdminLoc1(self.config.get_admin_loc1().unwrap_or_default()));
+                let _ = self.writer.write(RPL::AdminLoc2(self.config.get_admin_loc2().unwrap_or_default()));
+                let _ = self.writer.write(RPL::AdminEmail(self.config.get_admin_email().unwrap_or_default()));
+            },
+            (State::Connected{..}, "INFO") => {
+                let _ = self.writer.write(RPL::Info(VERSION.into()));
+                let _ = self.writer.write(RPL::Info(format!("Built for {}-{} ({})",
+                    env::consts::ARCH,
+                    env::consts::OS,
+                    if cfg!(debug_assertions) { "debug" } else { "release" },
+                )));
+                let _ = self.writer.write(RPL::EndOfInfo);
+            },
+            (State::Connected{..}, "STATS") => {
+                if !self.is_oper() {
+                    let _ = self.writer.write(RPL::NoPrivileges);
+                    return false;
+                }
+                let letter = cmd.params.iter().chain(cmd.trailing.iter()).next().and_then(|arg| arg.chars().next());
+                match letter {
+                    Some(letter) => stats::report(letter, self.writer.clone(), self.directory.clone(), self.config.clone()),
+                    None => {
+                        let _ = self.writer.write(RPL::NeedMoreParams("STATS".into()));
+                    },
+                }
+            },
+            (State::Connected{data}, "WHOIS") => {
+                // WHOIS [server] <nick>, only local lookups are supported so the server is ignored
+                match cmd.params.last().or(cmd.trailing.first()) {
+                    Some(target) => whois::report(target.clone(), data.nick(), self.is_oper(), self.writer.clone(), self.directory.clone(), self.config.clone()),
+                    None => {
+                        let _ = self.writer.write(RPL::NeedMoreParams("WHOIS".into()));
+                    },
+                }
+            },
+            (State::Connected{data}, "REHASH") => {
+                if !self.is_oper() {
+                    let _ = self.writer.write(RPL::NoPrivileges);
+                    return false;
+                }
+                match self.config.rehash() {
+                    Ok(path) => {
+                        let _ = self.writer.write(RPL::Rehashing(path.clone()));
+                        let _ = self.directory.server_notice('s', format!("{} is rehashing server config file {}", data.nick(), path));
+                    },
+                    Err(ConfigError::ConfigError(e)) => {
+                        let _ = self.writer.write(RPL::ServerNotice(format!("Rehash failed, keeping the current config: {}", e)));
+                    },
+                    Err(e) => {
+                        lprintln!("Internal error rehashing: {:?}", e);
+                    },
+                }
+            },
+            (State::Connected{data}, "DIE") => {
+                self.stop_server(data, Stop::Die);
+            },
+            (State::Connected{data}, "RESTART") => {
+                self.stop_server(data, Stop::Restart);
+            },
+            (State::Connected{data}, "KLINE") => {
+                self.add_ban(BanKind::KLine, cmd, data);
+            },
+            (State::Connected{data}, "GLINE") => {
+                self.add_ban(BanKind::GLine, cmd, data);
+            },
+            (State::Connected{data}, "ZLINE") => {
+                self.add_ban(BanKind::ZLine, cmd, data);
+            },
+            (State::Connected{data}, "UNKLINE") => {
+                self.remove_ban(BanKind::KLine, cmd, data);
+            },
+            (State::Connected{data}, "UNGLINE") => {
+                self.remove_ban(BanKind::GLine, cmd, data);
             },
-            (State::Connected{data}, "WHO") => {
+            (State::Connected{data}, "UNZLINE") => {
+                self.remove_ban(BanKind::ZLine, cmd, data);
+            },
+            (State::Connected{data}, "KILL") => {
+                if !self.is_oper() {
+                    let _ = self.writer.write(RPL::NoPrivileges);
+                    return false;
+                }
+                if cmd.params.len() == 0 {
+                    let _ = self.writer.write(RPL::NeedMoreParams("KILL".into()));
+                    return false;
+                }
+                let target = cmd.params[0].clone();
+                let reason = match cmd.trailing.len() {
+                    0 => Reason::from("No reason provided"),
+                    _ => Reason::from(cmd.trailing.join(" ")),
+                };
+                match self.find_user(&target) {
+                    Some(user) => {
+                        let _ = self.directory.server_notice('k', format!("Received KILL message for {}. From {} ({})", target, data.nick(), reason));
+                        let _ = user.kill(data.nick(), reason);
+                    }
+                    None => {
+                        let _ = self.writer.write(RPL::NickNotFound(target));
+                    }
+                }
+            },
+            (State::Connected{..}, "WHO") => {
                 // TODO: should send back a list of the users within a channel
+                if cmd.params.len() == 0 {
+                    let _ = self.writer.write(RPL::NeedMoreParams("WHO".into()));
+                    return false;
+                }
                 match self.get_communicable(&cmd.params[0]) {
                     Communicable::Channel(Some(channel)) => {
-                        channel.who().unwrap();
+                        let _ = channel.who();
                     },
                     Communicable::Channel(None) => {
                         lprintln!("Cannot get WHO for a channel we're not in");
@@ -294,37 +762,51 @@ impl<'a> UserWorker<'a> {
                 }
             },
             (State::Connected{data}, "PRIVMSG") => {
-                let msg_string = cmd.params.split_at(1).1.join(" ") + cmd.trailing.join(" ").as_ref();
+                if cmd.params.len() == 0 {
+                    let _ = self.writer.write(RPL::NeedMoreParams("PRIVMSG".into()));
+                    return false;
+                }
+                let msg_string = MessageText::from(cmd.text_from(1));
                 match self.get_communicable(&cmd.params[0]) {
                     Communicable::Channel(Some(channel)) => {
-                        channel.privmsg(data.gen_mask(&self.config).for_privmsg(), msg_string);
+                        let _ = channel.privmsg(data.gen_mask(&self.server_name).for_privmsg(), msg_string);
                     },
                     Communicable::Channel(None) => {
-                        // find out what's supposed to happen when PRIVMSG a channel the user isn't in
-                        unimplemented!{};
+                        // no outside messages, so only members can send to a channel
+                        let _ = self.writer.write(RPL::CannotSendToChan(cmd.params[0].clone()));
                     },
                     Communicable::User(Some(user)) => {
-                        user.privmsg(data.gen_mask(&self.config).for_privmsg(), msg_string);
+                        let _ = user.privmsg(data.gen_mask(&self.server_name).for_privmsg(), msg_string);
                     },
                     Communicable::User(None) => {
-                        self.writer.write(RPL::NickNotFound(cmd.params[0].clone()));
+                        let _ = self.writer.write(RPL::NickNotFound(cmd.params[0].clone()));
                     },
                 };
             },
             (State::Connected{data}, "JOIN") => {
-                let name = cmd.params[0].clone();
+                if cmd.params.len() == 0 {
+                    let _ = self.writer.write(RPL::NeedMoreParams("JOIN".into()));
+                    return false;
+                }
+                let name = match ChannelName::parse(&cmd.params[0]) {
+                    Some(name) => name,
+                    None => {
+                        let _ = self.writer.write(RPL::NoSuchChannel(cmd.params[0].clone()));
+                        return false;
+                    },
+                };
                 if self.is_in_channel(&name) {
                     lprintln!("Already in channel, doing nothing");
                     return false;
                 }
-                match self.directory.get_channel_by_name(name.clone(), data.nick.clone()) {
+                match self.directory.get_channel_by_name(name.clone(), data.nick()) {
                     Ok(channel) => {
                         lprintln!("Got channel: {:?}", channel);
-                        match self.directory.get_user_by_nick(data.nick.clone()) {
+                        match self.directory.get_user_by_nick(data.nick()) {
                             Ok(user) => {
                                 match channel.join(user) {
                                     Ok(entry) => {
-                                        entry.update_mask(data.nick.clone());
+                                        entry.update_mask(data.gen_mask(&self.server_name).for_privmsg());
                                         self.channels.push(StoredChannel{
                                             name: name.clone(),
                                             thread: entry,
@@ -345,15 +827,26 @@ impl<'a> UserWorker<'a> {
                     }
                 };
             },
-            (State::Connected{data}, "PART") => {
+            (State::Connected{..}, "PART") => {
                 let (name, reason) = match cmd.params.len() {
+                    0 if cmd.trailing.len() == 0 => {
+                        let _ = self.writer.write(RPL::NeedMoreParams("PART".into()));
+                        return false;
+                    }
                     0 => {
                         (cmd.trailing[0].clone(), None)
                     }
                     _ => {
-                        (cmd.params[0].clone(), Some(cmd.trailing.join(" ")))
+                        (cmd.params[0].clone(), Some(Reason::from(cmd.trailing.join(" "))))
                     }
                 };
+                let name = match ChannelName::parse(&name) {
+                    Some(name) => name,
+                    None => {
+                        let _ = self.writer.write(RPL::NoSuchChannel(name));
+                        return false;
+                    },
+                };
                 lprintln!("Looking for channel: {:?}", name);
                 if !self.is_in_channel(&name) {
                     lprintln!("Not in channel, doing nothing");
@@ -361,7 +854,7 @@ impl<'a> UserWorker<'a> {
                 }
                 lprintln!("Draining");
                 let drained = self.channels.drain(..).filter(|c| {
-                    if c.name == *name {
+                    if c.name == name {
                         c.thread.part_reason(reason.clone());
                         false
                     } else {
@@ -373,6 +866,9 @@ impl<'a> UserWorker<'a> {
 
             },
             (_, "QUIT") => {
+                if cmd.trailing.len() > 0 {
+                    self.quit_reason = format!("Quit: {}", cmd.trailing.join(" ")).into();
+                }
                 return true;
             },
             (_,_) => {
@@ -383,62 +879,398 @@ impl<'a> UserWorker<'a> {
         return false;
     }
 
-    fn introduce(&mut self, data: &UserData) {
+    fn try_register(&mut self, mut data: UserData) -> bool {
+        // registration completes once NICK and USER have been sent and the hostname and ident are known
+        self.state = if data.is_ready() && self.hostname.is_some() && !self.ident_pending {
+            data.host = self.hostname.clone().unwrap();
+            data.real_host = data.host.clone();
+            // an uncloaked host would give away the real one, so no cloak to be had means no registering
+            data.cloaked_host = match self.config.cloak_host(data.real_host.clone()) {
+                Ok(cloaked_host) => cloaked_host,
+                Err(e) => {
+                    lprintln!("Internal error cloaking host: {:?}", e);
+                    return self.refuse("Try again later");
+                },
+            };
+            lprintln!("== Connected");
+            let _ = self.writer.update_nick(data.nick());
+            if !self.check_pass() {
+                self.quit_reason = "Bad Password".into();
+                let _ = self.writer.write(RPL::PasswdMismatch);
+                let _ = self.writer.write(RPL::ClosingLink(self.quit_reason.clone()));
+                return true;
+            }
+            let has_collisions = self.directory_entry.update_nick(data.nick());
+            lprintln!("GOT BACK: {:?}", has_collisions);
+            match has_collisions {
+                Ok(_) => {
+                    lprintln!("Nick has no collisions, good to continue");
+                }
+                Err(channel_traits_error::NickCollision) => {
+                    lprintln!("Nick has collisions, cannot continue");
+                    let _ = self.writer.write(RPL::NickInUse);
+                    self.state = State::NewConnection(Some(data));
+                    return false;
+                }
+                Err(e) => {
+                    lprintln!("Internal error determining if nick has collisions: {:?}", e);
+                    self.state = State::NewConnection(Some(data));
+                    return false;
+                }
+            }
+            // only changed once the nick is accepted, a retried registration would add another ~
+            data.user_name = ident::user_name(&self.ident, &data.user_name);
+            let mask = data.gen_mask(&self.server_name);
+            match self.directory.find_ban(vec![BanKind::KLine, BanKind::GLine, BanKind::ZLine], mask.user, mask.real_host, self.info.ip.clone()) {
+                Ok(Some(ban)) => {
+                    self.reject_ban(ban);
+                    return true;
+                },
+                Ok(None) => {},
+                Err(e) => {
+                    lprintln!("Internal error checking bans: {:?}", e);
+                },
+            }
+            // users are cloaked from the start when cloaking is enabled
+            if let Some(ref cloak) = data.cloaked_host {
+                data.host = cloak.clone();
+            }
+            data.timestamp = Timestamp::now();
+            self.keepalive.clear_deadline();
+            self.introduce(&data);
+            self.welcome(&data);
+            let _ = self.directory.server_notice('c', format!("Client connecting: {}", data.gen_mask(&self.server_name).for_notice()));
+            if let Some(account) = self.account.clone() {
+                let _ = self.writer.write(RPL::LoggedIn(data.gen_mask(&self.server_name).for_privmsg(), account));
+            }
+            State::Connected{data: data}
+        } else {
+            State::NewConnection(Some(data))
+        };
+        false
+    }
+
+    // PASS is either the class password or, bouncer style, account:password for an account block.
+    // Logging in to an account also gets a client past its class password. A failed login is
+    // reported, and only costs the connection when the class has a password the client didn't give.
+    fn check_pass(&mut self) -> bool {
+        let pass = match self.pass.clone() {
+            Some(pass) => pass,
+            None => return self.class.password.is_none(),
+        };
+        if self.class.password.as_ref() == Some(&pass) {
+            return true;
+        }
+        let mut parts = pass.splitn(2, ':');
+        if let (Some(account), Some(password)) = (parts.next(), parts.next()) {
+            match self.config.check_account(account.into(), password.into()) {
+                Ok(true) => {
+                    self.account = Some(account.into());
+                    return true;
+                },
+                Ok(false) => {
+                    let _ = self.directory.server_notice('c', format!("Failed PASS login by {} using account {}", self.notice_name(), account));
+                },
+                Err(e) => {
+                    lprintln!("Internal error checking account: {:?}", e);
+                },
+            }
+        }
+        self.class.password.is_none()
+    }
+
+    fn introduce(&mut self, _data: &UserData) {
         //TODO: broadcast to the other servers information about this user, refer to seven src/s_user.c introduce_client
     }
 
     fn welcome(&mut self, data: &UserData) {
         // upon first connect send the user this information
-        self.writer.write(RPL::Welcome{msg: "Hello, World!".into()});
-        self.writer.write(RPL::YourHost);
+        let _ = self.writer.write(RPL::Welcome{msg: "Hello, World!".into()});
+        let _ = self.writer.write(RPL::YourHost);
+        self.isupport();
+        self.lusers();
         self.motd();
         self.set_mode('i');
+        if self.info.tls {
+            self.set_mode('z');
+        }
+        if data.cloaked_host.is_some() {
+            self.set_mode('x');
+            let _ = self.writer.write(RPL::HostHidden(data.host.clone()));
+        }
+        self.update_directory_modes();
+    }
+
+    fn isupport(&mut self) {
+        let _ = self.writer.write(RPL::ISupport(vec![
+            "CHANTYPES=#".into(),
+            "PREFIX=(o)@".into(),
+            "CASEMAPPING=ascii".into(),
+            format!("NICKLEN={}", NICKLEN),
+            format!("CHANNELLEN={}", CHANNELLEN),
+        ]));
+    }
+
+    fn lusers(&mut self) {
+        let lusers = match self.directory.get_lusers() {
+            Ok(lusers) => lusers,
+            Err(e) => {
+                lprintln!("Error getting lusers: {:?}", e);
+                return;
+            }
+        };
+        let global = lusers.local_users + lusers.virtual_users;
+        let _ = self.writer.write(RPL::LuserClient(global - lusers.invisible, lusers.invisible, lusers.servers + 1));
+        let _ = self.writer.write(RPL::LuserOp(lusers.opers));
+        let _ = self.writer.write(RPL::LuserUnknown(lusers.unknown));
+        let _ = self.writer.write(RPL::LuserChannels(lusers.channels));
+        let _ = self.writer.write(RPL::LuserMe(lusers.local_users, lusers.servers));
+        let _ = self.writer.write(RPL::LocalUsers(lusers.local_users, lusers.local_max));
+        let _ = self.writer.write(RPL::GlobalUsers(global, lusers.global_max));
     }
 
     fn motd(&mut self) {
-        self.writer.write(RPL::MotdStart);
-        self.writer.write(RPL::Motd("Hello MOTD".into()));
-        self.writer.write(RPL::MotdEnd);
+        let _ = self.writer.write(RPL::MotdStart);
+        let _ = self.writer.write(RPL::Motd("Hello MOTD".into()));
+        let _ = self.writer.write(RPL::MotdEnd);
     }
 
     fn set_mode(&mut self, mode: char) {
         if !self.modes.contains(&mode) {
             self.modes.push(mode);
         }
-        self.writer.write(RPL::ModeSelf{mode: mode, enabled: true});
+        let _ = self.writer.write(RPL::ModeSelf{mode: mode, enabled: true});
     }
     
     fn remove_mode(&mut self, mode: char) {
         self.modes.retain(|e| (*e) != mode);
-        self.writer.write(RPL::ModeSelf{mode: mode, enabled: false});
+        let _ = self.writer.write(RPL::ModeSelf{mode: mode, enabled: false});
+    }
+
+    // swaps the displayed host between the cloak and the real host
+    fn set_cloak(&mut self, enabled: bool) {
+        if enabled == self.modes.contains(&'x') {
+            return;
+        }
+        let host = match self.state {
+            State::Connected{ref mut data} => match data.cloaked_host.clone() {
+                Some(cloak) => {
+                    data.host = if enabled { cloak } else { data.real_host.clone() };
+                    data.host.clone()
+                },
+                // cloaking isn't enabled on this server
+                None => return,
+            },
+            _ => return,
+        };
+        if enabled {
+            self.set_mode('x');
+        } else {
+            self.remove_mode('x');
+        }
+        let _ = self.writer.write(RPL::HostHidden(host));
+        self.update_channel_masks();
+    }
+
+    fn is_oper(&self) -> bool {
+        self.modes.contains(&'o')
+    }
+
+    // every connection is closed, this one included, once the server is on its way down
+    fn stop_server(&mut self, data: UserData, how: Stop) {
+        if !self.is_oper() {
+            let _ = self.writer.write(RPL::NoPrivileges);
+            return;
+        }
+        let command = match how {
+            Stop::Restart => "RESTART",
+            _ => "DIE",
+        };
+        ::util::stop(format!("{} by {}", command, data.nick()), how);
+    }
+
+    // KLINE <user@host> [duration] :<reason>, the same form is used for GLINE and ZLINE (with an ip)
+    fn add_ban(&mut self, kind: BanKind, cmd: ParsedCommand, data: UserData) {
+        let command = cmd.command.to_uppercase();
+        if !self.is_oper() {
+            let _ = self.writer.write(RPL::NoPrivileges);
+            return;
+        }
+        if cmd.params.len() == 0 {
+            let _ = self.writer.write(RPL::NeedMoreParams(command));
+            return;
+        }
+        let duration = match cmd.params.get(1) {
+            Some(duration) => match parse_duration(duration) {
+                Some(duration) => duration,
+                None => {
+                    let _ = self.writer.write(RPL::ServerNotice(format!("Invalid duration for {}: {}", command, duration)));
+                    return;
+                }
+            },
+            None => 0,
+        };
+        let reason = match cmd.trailing.len() {
+            0 => "No reason provided".into(),
+            _ => cmd.trailing.join(" "),
+        };
+        let ban = Ban::new(kind, &cmd.params[0], duration, reason, data.nick().to_string());
+        let _ = self.directory.add_ban(None, ban);
+    }
+
+    fn remove_ban(&mut self, kind: BanKind, cmd: ParsedCommand, data: UserData) {
+        if !self.is_oper() {
+            let _ = self.writer.write(RPL::NoPrivileges);
+            return;
+        }
+        if cmd.params.len() == 0 {
+            let _ = self.writer.write(RPL::NeedMoreParams(cmd.command.to_uppercase()));
+            return;
+        }
+        // normalise a bare host into the same user@host form the ban was stored under
+        let mask = Ban::new(kind, &cmd.params[0], 0, "".into(), data.nick().to_string()).mask();
+        match self.directory.remove_ban(None, kind, mask.clone(), data.nick().to_string()) {
+            Ok(true) => {},
+            _ => {
+                let _ = self.writer.write(RPL::ServerNotice(format!("No such {}: {}", kind.name(), mask)));
+            },
+        }
+    }
+
+    fn ban_matches(&self, ban: &Ban) -> bool {
+        match &self.state {
+            &State::Connected{ref data} => {
+                let mask = data.gen_mask(&self.server_name);
+                ban.matches(&mask.user, &mask.real_host, &self.info.ip)
+            },
+            _ => ban.kind == BanKind::ZLine && ban.matches("*", &self.info.ip, &self.info.ip),
+        }
+    }
+
+    fn reject_ban(&mut self, ban: Ban) {
+        self.quit_reason = format!("{}d: {}", ban.kind.name(), ban.reason).into();
+        let _ = self.writer.write(RPL::YoureBanned(ban.reason.clone().into()));
+        let _ = self.writer.write(RPL::ClosingLink(self.quit_reason.clone()));
     }
 
-    fn get_communicable(&mut self, name: &String) -> Communicable {
+    fn apply_user_modes(&mut self, args: Vec<String>) {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut enabled = true;
+            for mode in arg.chars() {
+                match (enabled, mode) {
+                    (_, '+') => { enabled = true; },
+                    (_, '-') => { enabled = false; },
+                    // +o can only be gained through OPER
+                    (true, 'o') => {},
+                    (false, 'o') => {
+                        self.remove_mode('o');
+                        if self.modes.contains(&'s') {
+                            self.remove_mode('s');
+                        }
+                        self.snomask.clear();
+                    },
+                    (true, 's') => {
+                        if !self.is_oper() {
+                            let _ = self.writer.write(RPL::NoPrivileges);
+                            continue;
+                        }
+                        self.set_mode('s');
+                        // the snomask itself is carried in the following argument, eg MODE nick +s +cklo
+                        let spec = args.next().unwrap_or(SNOMASKS.into());
+                        self.apply_snomask(spec);
+                    },
+                    (false, 's') => {
+                        self.remove_mode('s');
+                        self.snomask.clear();
+                    },
+                    // +z reflects how the client connected, it can't be changed
+                    (_, 'z') => {},
+                    (_, 'x') => self.set_cloak(enabled),
+                    (true, 'i') | (true, 'w') => self.set_mode(mode),
+                    (false, 'i') | (false, 'w') => self.remove_mode(mode),
+                    _ => {
+                        let _ = self.writer.write(RPL::UModeUnknownFlag);
+                    }
+                }
+            }
+        }
+        self.update_directory_modes();
+    }
+
+    fn apply_snomask(&mut self, spec: String) {
+        let mut enabled = true;
+        for mask in spec.chars() {
+            match mask {
+                '+' => enabled = true,
+                '-' => enabled = false,
+                mask if SNOMASKS.contains(mask) => {
+                    if enabled && !self.snomask.contains(&mask) {
+                        self.snomask.push(mask);
+                    } else if !enabled {
+                        self.snomask.retain(|e| (*e) != mask);
+                    }
+                },
+                _ => {},
+            }
+        }
+        let snomask: String = self.snomask.iter().cloned().collect();
+        let _ = self.writer.write(RPL::SnoMask(snomask));
+    }
+
+    fn update_directory_modes(&mut self) {
+        let _ = self.directory_entry.update_modes(self.modes.clone(), self.snomask.clone());
+    }
+
+    fn get_communicable(&mut self, name: &str) -> Communicable {
         match name.chars().next().to_owned() {
             Some('#') => {
-                match self.get_channel(name) {
+                match ChannelName::parse(name).and_then(|name| self.get_channel(&name)) {
                     Some(channel) => {
                         Communicable::Channel(Some(channel.thread.clone()))
                     }
                     None => Communicable::Channel(None),
                 }
             },
-            _ => {
-                match self.directory.get_user_by_nick(name.clone()) {
-                    Ok(user) => {
-                        Communicable::User(Some(user))
-                    }
-                    Err(_) => Communicable::User(None)
-                }
+            _ => Communicable::User(self.find_user(name)),
+        }
+    }
+
+    fn find_user(&self, nick: &str) -> Option<User> {
+        Nick::parse(nick).and_then(|nick| self.directory.get_user_by_nick(nick).ok())
+    }
+
+    // replies with ERR_ERRONEUSNICKNAME when the nick NICK was sent with isn't valid
+    fn parse_nick(&mut self, cmd: &ParsedCommand) -> Option<Nick> {
+        let nick = match cmd.params.first().or(cmd.trailing.first()) {
+            Some(nick) => nick,
+            None => {
+                let _ = self.writer.write(RPL::NeedMoreParams("NICK".into()));
+                return None;
+            },
+        };
+        let parsed = Nick::parse(nick);
+        if parsed.is_none() {
+            let _ = self.writer.write(RPL::ErroneousNickname(nick.clone()));
+        }
+        parsed
+    }
+
+    // the channels part the user with its mask, which changes with the nick and the cloak
+    fn update_channel_masks(&self) {
+        if let State::Connected{ref data} = self.state {
+            let mask = data.gen_mask(&self.server_name).for_privmsg();
+            for channel in self.channels.iter() {
+                channel.thread.update_mask(mask.clone());
             }
         }
     }
 
-    fn get_channel(&mut self, name: &String) -> Option<&StoredChannel> {
+    fn get_channel(&self, name: &ChannelName) -> Option<&StoredChannel> {
         self.channels.iter().find(|c| c.name == *name)
     }
 
-    fn is_in_channel(&mut self, name: &String) -> bool{
+    fn is_in_channel(&self, name: &ChannelName) -> bool{
         self.get_channel(name).is_some()
     }
 }
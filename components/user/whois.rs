@@ -0,0 +1,57 @@
+use std::thread;
+
+use net_traits::{Writer, RPL};
+use channel_traits::{Directory, Error};
+use server_traits::Config;
+use util::Nick;
+
+// like STATS, WHOIS runs on its own thread so a user can WHOIS itself without its UserWorker
+// having to answer its own GetWhois query
+pub fn report(target: String, requester: Nick, requester_is_oper: bool, writer: Writer, directory: Directory, config: Config) {
+    let _ = thread::Builder::new().name("WhoisThread".to_string()).spawn(move || {
+        let user = match Nick::parse(&target) {
+            Some(nick) => directory.get_user_by_nick(nick),
+            None => Err(Error::NickNotFound),
+        };
+        let whois = match user {
+            Ok(user) => user.get_whois(),
+            Err(_) => {
+                let _ = writer.write(RPL::NickNotFound(target.clone()));
+                let _ = writer.write(RPL::EndOfWhois(target));
+                return;
+            },
+        };
+        match whois {
+            Ok(whois) => {
+                let mask = whois.mask;
+                let _ = writer.write(RPL::WhoisUser(mask.nick.clone(), mask.user.clone(), mask.host.clone(), mask.real.clone()));
+                if whois.channels.len() > 0 {
+                    let _ = writer.write(RPL::WhoisChannels(mask.nick.clone(), whois.channels));
+                }
+                let info = match config.get_server_name() {
+                    Ok(ref name) if *name == mask.servername => config.get_server_desc().unwrap_or_default(),
+                    _ => "".into(),
+                };
+                let _ = writer.write(RPL::WhoisServer(mask.nick.clone(), mask.servername.clone(), info));
+                if whois.modes.contains(&'o') {
+                    let _ = writer.write(RPL::WhoisOperator(mask.nick.clone()));
+                }
+                if whois.modes.contains(&'z') {
+                    let _ = writer.write(RPL::WhoisSecure(mask.nick.clone()));
+                }
+                // the real host and the fingerprint identify the user, so only they and opers get to see them
+                if requester == mask.nick || requester_is_oper {
+                    let _ = writer.write(RPL::WhoisHost(mask.nick.clone(), mask.real_host.clone()));
+                    if let Some(certfp) = whois.certfp {
+                        let _ = writer.write(RPL::WhoisCertFP(mask.nick.clone(), certfp));
+                    }
+                }
+            },
+            Err(_) => {
+                // the user is still registering
+                let _ = writer.write(RPL::NickNotFound(target.clone()));
+            },
+        }
+        let _ = writer.write(RPL::EndOfWhois(target));
+    });
+}
@@ -0,0 +1,173 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BanKind {
+    KLine, // user@host, local to this server
+    GLine, // user@host, network-wide
+    ZLine, // ip, checked before registration
+}
+
+impl BanKind {
+    pub fn letter(&self) -> char {
+        match self {
+            &BanKind::KLine => 'K',
+            &BanKind::GLine => 'G',
+            &BanKind::ZLine => 'Z',
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            &BanKind::KLine => "K-Line",
+            &BanKind::GLine => "G-Line",
+            &BanKind::ZLine => "Z-Line",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ban {
+    pub kind: BanKind,
+    pub user: String, // always "*" for Z-Lines
+    pub host: String, // hostname or ip mask
+    pub reason: String,
+    pub set_by: String,
+    pub set_at: u64,
+    pub expires_at: Option<u64>, // None is permanent
+}
+
+impl Ban {
+    // mask is either user@host or a bare host, in which case any user matches
+    pub fn new(kind: BanKind, mask: &str, duration: u64, reason: String, set_by: String) -> Self {
+        let (user, host) = match mask.find('@') {
+            Some(i) if kind != BanKind::ZLine => (mask[..i].to_string(), mask[i+1..].to_string()),
+            _ => ("*".to_string(), mask.to_string()),
+        };
+        let now = unix_time();
+        Ban{
+            kind: kind,
+            user: user,
+            host: host,
+            reason: reason,
+            set_by: set_by,
+            set_at: now,
+            // a duration too long to add up to a time is as good as permanent, but kept apart from it
+            expires_at: if duration == 0 { None } else { Some(now.saturating_add(duration)) },
+        }
+    }
+
+    pub fn mask(&self) -> String {
+        match self.kind {
+            BanKind::ZLine => self.host.clone(),
+            _ => format!("{}@{}", self.user, self.host),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= unix_time(),
+            None => false,
+        }
+    }
+
+    pub fn matches(&self, user: &str, host: &str, ip: &str) -> bool {
+        if self.is_expired() {
+            return false;
+        }
+        match self.kind {
+            BanKind::ZLine => wildcard_match(&self.host, ip),
+            _ => wildcard_match(&self.user, user) && (wildcard_match(&self.host, host) || wildcard_match(&self.host, ip)),
+        }
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// case-insensitive match supporting * (any run of characters) and ? (any single character)
+pub fn wildcard_match(pattern: &str, subject: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let subject: Vec<char> = subject.to_lowercase().chars().collect();
+    let (mut p, mut s) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while s < subject.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == subject[s]) {
+            p += 1;
+            s += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, s));
+            p += 1;
+        } else if let Some((bp, bs)) = backtrack {
+            p = bp + 1;
+            s = bs + 1;
+            backtrack = Some((bp, bs + 1));
+        } else {
+            return false;
+        }
+    }
+    while p < pattern.len() && pattern[p] == '*' {
+        p += 1;
+    }
+    p == pattern.len()
+}
+
+// Durations are either plain seconds or a combination of units, eg 1d12h or 30m. 0 is permanent, and
+// one that doesn't fit in a u64 isn't a duration.
+pub fn parse_duration(duration: &str) -> Option<u64> {
+    if let Ok(secs) = duration.parse() {
+        return Some(secs);
+    }
+    let mut total: u64 = 0;
+    let mut current = String::new();
+    for c in duration.chars() {
+        let multiplier = match c {
+            c if c.is_ascii_digit() => {
+                current.push(c);
+                continue;
+            },
+            'w' => 7 * 24 * 60 * 60,
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        let amount: u64 = match current.parse() {
+            Ok(amount) => amount,
+            Err(_) => return None,
+        };
+        total = amount.checked_mul(multiplier).and_then(|secs| total.checked_add(secs))?;
+        current.clear();
+    }
+    if current.len() > 0 || duration.len() == 0 {
+        return None;
+    }
+    Some(total)
+}
+
+#[test]
+fn wildcard_match_test() {
+    assert!(wildcard_match("*", "anything"));
+    assert!(wildcard_match("*.example.com", "irc.EXAMPLE.com"));
+    assert!(wildcard_match("192.168.?.*", "192.168.1.20"));
+    assert!(!wildcard_match("*.example.com", "example.com"));
+    assert!(!wildcard_match("bad?", "bad"));
+}
+
+#[test]
+fn parse_duration_test() {
+    assert_eq!(parse_duration("0"), Some(0));
+    assert_eq!(parse_duration("3600"), Some(3600));
+    assert_eq!(parse_duration("1d12h"), Some(36 * 60 * 60));
+    assert_eq!(parse_duration("30m"), Some(30 * 60));
+    assert_eq!(parse_duration("user@host"), None);
+    assert_eq!(parse_duration("5x"), None);
+    assert_eq!(parse_duration("9999999999999999999w"), None);
+    assert_eq!(parse_duration("18446744073709551615s1s"), None);
+
+    // a duration near u64::MAX can't wrap round into a ban that has already expired
+    let ban = Ban::new(BanKind::KLine, "*@host", u64::MAX, "".into(), "".into());
+    assert_eq!(ban.expires_at, Some(u64::MAX));
+    assert!(ban.matches("user", "host", "192.0.2.1"));
+}
@@ -7,6 +7,7 @@ pub type Result<T> = result::Result<T, Error>;
 pub enum Error {
     SendError(&'static str),
     RecvError(&'static str),
+    Timeout(&'static str),
     MalformedString,
     InvalidState,
 }
@@ -16,6 +17,7 @@ impl From<ChanError> for Error {
         match err {
             ChanError::SendError(err) => Error::SendError(err),
             ChanError::RecvError(err) => Error::RecvError(err),
+            ChanError::Timeout(err) => Error::Timeout(err),
         }
     }
 }
@@ -4,6 +4,8 @@ extern crate net_traits;
 
 pub mod user_thread;
 pub mod error;
+pub mod ban;
 
 pub use user_thread::*;
 pub use error::*;
+pub use ban::*;
@@ -1,24 +1,28 @@
-use std::sync::mpsc::{channel, Sender};
-use super::Result;
-
-pub type UserThread = Sender<UserThreadMsg>;
+use super::Error;
+use net_traits::ConnectionStats;
+use super::Ban;
+use util::{Nick, ChannelName, Hostmask, Reason, MessageText, Timestamp};
 
 #[derive(Debug, Clone)]
 pub struct Mask {
-    pub nick: String,
+    pub nick: Nick,
     pub user: String,
-    pub host: String,
+    pub host: String, // As shown to other users, the cloak when the user is +x
+    pub real_host: String,
+    pub cloaked_host: Option<String>,
     pub real: String,
     pub hops: u32,
-    pub timestamp: String,
+    pub timestamp: Timestamp,
     pub servername: String,
 }
 
 impl Mask {
-    pub fn new(nick: String, user: String, host: String, real: String, hops: u32, timestamp: String, servername: String) -> Self {
+    pub fn new(nick: Nick, user: String, host: String, real: String, hops: u32, timestamp: Timestamp, servername: String) -> Self {
         Mask{
             nick: nick,
             user: user,
+            real_host: host.clone(),
+            cloaked_host: None,
             host: host,
             real: real,
             hops: hops,
@@ -28,94 +32,54 @@ impl Mask {
     }
     pub fn full(&self) -> String {
         let mut ret = String::new();
-        ret.push_str(self.nick.as_ref());
-        ret.push_str("!");
+        ret.push_str(self.nick.as_str());
+        ret.push('!');
         ret.push_str(self.user.as_ref());
-        ret.push_str("@");
+        ret.push('@');
         ret.push_str(self.host.as_ref());
         ret.push_str(" * ");
         ret.push_str(self.real.as_ref());
         ret
     }
-    pub fn for_privmsg(&self) -> String {
-        let mut ret = String::new();
-        ret.push_str(self.nick.as_ref());
-        ret.push_str("!");
-        ret.push_str(self.user.as_ref());
-        ret.push_str("@");
-        ret.push_str(self.host.as_ref());
-        ret
+    pub fn for_privmsg(&self) -> Hostmask {
+        Hostmask::new(&self.nick, &self.user, &self.host)
+    }
+    // server notices go to opers, who get to see the real host
+    pub fn for_notice(&self) -> String {
+        format!("{} ({}@{})", self.nick, self.user, self.real_host)
     }
-}
-
-#[derive(Debug)]
-pub enum UserThreadMsg {
-    Privmsg(String, String), // Src Mask, Msg
-    PrivmsgChan(String, String, String), // Mask, Channel, Msg
-    JoinSelf(String),
-    PartSelf(String, String), // Channel, Reason
-    JoinOther(String, String), // Mask,  Channel
-    PartOther(String, String, String), // Mask, Channel, Reason
-    GetMask(Sender<Result<Mask>>),
-    TransmitNames(String, Vec<String>), // Channel, Names
-    Exit,
 }
 
 #[derive(Debug, Clone)]
-pub struct User {
-    thread: UserThread,
+pub struct Whois {
+    pub mask: Mask,
+    pub modes: Vec<char>,
+    pub channels: Vec<ChannelName>,
+    pub certfp: Option<String>,
 }
 
-impl User {
-    pub fn new(thread: UserThread) -> Self {
-        User{
-            thread: thread,
-        }
-    }
-
-    /*
-    pub fn send_command(&self, cmd: ParsedCommand) -> Result<()>{
-        try!(send!(self.thread, UserThreadMsg::Command => (cmd)));
-        Ok(())
-    }
-    */
-
-    pub fn privmsg(&self, src: String, msg: String) -> Result<()> {
-        try!(send!(self.thread, UserThreadMsg::Privmsg => (src, msg)));
-        Ok(())
-    }
-    
-    pub fn privmsg_chan(&self, src: String, chan: String, msg: String) -> Result<()> {
-        try!(send!(self.thread, UserThreadMsg::PrivmsgChan => (src, chan, msg)));
-        Ok(())
-    }
-
-    pub fn inform_self_join(&self, channel: String) -> Result<()> {
-        try!(send!(self.thread, UserThreadMsg::JoinSelf => (channel)));
-        Ok(())
-    }
-
-    pub fn inform_self_part(&self, channel: String, reason: String) -> Result<()> { 
-        try!(send!(self.thread, UserThreadMsg::PartSelf => (channel, reason)));
-        Ok(())
-    }
-
-    pub fn inform_other_join(&self, mask: String, channel: String) -> Result<()> {
-        try!(send!(self.thread, UserThreadMsg::JoinOther => (mask, channel)));
-        Ok(())
-    }
-
-    pub fn inform_other_part(&self, mask: String, channel: String, reason: String) -> Result<()> {
-        try!(send!(self.thread, UserThreadMsg::PartOther => (mask, channel, reason)));
-        Ok(())
-    }
-
-    pub fn get_mask(&self) -> Result<Mask> {
-        Ok(try!(try!(req_rep!(self.thread, UserThreadMsg::GetMask => ()))))
-    }
-
-    pub fn transmit_names(&self, channel: String, names: Vec<String>) -> Result<()> {
-        try!(send!(self.thread, UserThreadMsg::TransmitNames => (channel, names)));
-        Ok(())
-    }
+actor!{
+    pub struct User(UserThread, UserThreadMsg) -> Error, timeout_ms 5000;
+    pub send fn privmsg(src: Hostmask, msg: MessageText) => Privmsg;
+    pub send fn privmsg_chan(src: Hostmask, chan: ChannelName, msg: MessageText) => PrivmsgChan;
+    pub send fn inform_self_join(channel: ChannelName) => JoinSelf;
+    pub send fn inform_self_part(channel: ChannelName, reason: Reason) => PartSelf;
+    pub send fn inform_other_join(mask: Hostmask, channel: ChannelName) => JoinOther;
+    pub send fn inform_other_part(mask: Hostmask, channel: ChannelName, reason: Reason) => PartOther;
+    pub try_req fn get_mask() -> Mask => GetMask;
+    pub try_req fn get_stats() -> ConnectionStats => GetStats;
+    pub try_req fn get_whois() -> Whois => GetWhois;
+    pub send fn transmit_names(channel: ChannelName, names: Vec<Nick>) => TransmitNames;
+    pub send fn server_notice(msg: String) => ServerNotice;
+    pub send fn wallops(src: Hostmask, msg: MessageText) => Wallops;
+    pub send fn globops(src: Nick, msg: MessageText) => Globops;
+    pub send fn kill(killer: Nick, reason: Reason) => Kill;
+    // the user disconnects itself if the ban matches it
+    pub send fn check_ban(ban: Ban) => CheckBan;
+    // None if the lookup failed
+    pub send fn host_resolved(host: Option<String>) => HostResolved;
+    // None if there was no ident response
+    pub send fn ident_resolved(user: Option<String>) => IdentResolved;
+    // the server is going away, the user is closed with the reason
+    pub send fn exit(reason: Reason) => Exit;
 }
@@ -0,0 +1,268 @@
+use std::thread;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::Duration;
+use mpsc::{Receiver, RecvTimeoutError};
+use ChanError;
+use supervisor::{Recovery, Stop, stop, panic_message};
+
+// Declares an actor: its message enum, the Sender type its thread is reached through, and a handle
+// with a method per message. Every line after the header is one message:
+//
+//   send fn     fire and forget, returns Result<(), Error>
+//   req fn      waits up to timeout_ms for the reply, returns Result<T, Error>
+//   try_req fn  the reply is a Result<T, Error> itself, which is flattened into the one returned
+//   call fn     returns T and panics if there's no reply, only for callers that are meant to die with the actor
+//   msg         a message without a method, for the ones wrapped by hand
+//
+// Error is the crate's own, converted from ChanError. Methods are private unless marked pub.
+//
+//   actor!{
+//       pub struct Channel(ChannelThread, ChannelThreadMsg) -> Error, timeout_ms 5000;
+//       send fn part(id: ChannelId, mask: String, reason: Option<String>) => Part;
+//       pub req fn get_name() -> String => GetName;
+//       msg Exit;
+//   }
+#[macro_export]
+macro_rules! actor {
+    (
+        pub struct $handle:ident($thread:ident, $msg:ident) -> $error:ty, timeout_ms $timeout:expr;
+        $($items:tt)*
+    ) => {
+        actor!(@items [$handle, $thread, $msg, $error, $timeout] [] [] $($items)*);
+    };
+
+    (@items [$handle:ident, $thread:ident, $msg:ident, $error:ty, $timeout:expr] [$($variants:tt)*] [$($methods:tt)*]
+        msg $variant:ident; $($rest:tt)*
+    ) => {
+        actor!(@items [$handle, $thread, $msg, $error, $timeout] [$($variants)* $variant,] [$($methods)*] $($rest)*);
+    };
+
+    (@items [$handle:ident, $thread:ident, $msg:ident, $error:ty, $timeout:expr] [$($variants:tt)*] [$($methods:tt)*]
+        msg $variant:ident($($ty:ty),*); $($rest:tt)*
+    ) => {
+        actor!(@items [$handle, $thread, $msg, $error, $timeout] [$($variants)* $variant($($ty),*),] [$($methods)*] $($rest)*);
+    };
+
+    (@items [$handle:ident, $thread:ident, $msg:ident, $error:ty, $timeout:expr] [$($variants:tt)*] [$($methods:tt)*]
+        $vis:vis send fn $name:ident() => $variant:ident; $($rest:tt)*
+    ) => {
+        actor!(@items [$handle, $thread, $msg, $error, $timeout] [$($variants)* $variant,] [$($methods)*
+            $vis fn $name(&self) -> ::std::result::Result<(), $error> {
+                self.thread.send($msg::$variant)
+                    .map_err(|_| $crate::ChanError::SendError(concat!(stringify!($msg), "::", stringify!($variant))).into())
+            }
+        ] $($rest)*);
+    };
+
+    (@items [$handle:ident, $thread:ident, $msg:ident, $error:ty, $timeout:expr] [$($variants:tt)*] [$($methods:tt)*]
+        $vis:vis send fn $name:ident($($arg:ident: $ty:ty),+) => $variant:ident; $($rest:tt)*
+    ) => {
+        actor!(@items [$handle, $thread, $msg, $error, $timeout] [$($variants)* $variant($($ty),+),] [$($methods)*
+            $vis fn $name(&self, $($arg: $ty),+) -> ::std::result::Result<(), $error> {
+                self.thread.send($msg::$variant($($arg),+))
+                    .map_err(|_| $crate::ChanError::SendError(concat!(stringify!($msg), "::", stringify!($variant))).into())
+            }
+        ] $($rest)*);
+    };
+
+    (@items [$handle:ident, $thread:ident, $msg:ident, $error:ty, $timeout:expr] [$($variants:tt)*] [$($methods:tt)*]
+        $vis:vis req fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty => $variant:ident; $($rest:tt)*
+    ) => {
+        actor!(@items [$handle, $thread, $msg, $error, $timeout] [$($variants)* $variant($crate::mpsc::Sender<$ret> $(, $ty)*),] [$($methods)*
+            $vis fn $name(&self $(, $arg: $ty)*) -> ::std::result::Result<$ret, $error> {
+                let (tx, rx) = $crate::mpsc::channel();
+                $crate::request(&self.thread, $msg::$variant(tx $(, $arg)*), rx, $timeout, concat!(stringify!($msg), "::", stringify!($variant)))
+                    .map_err(|e| e.into())
+            }
+        ] $($rest)*);
+    };
+
+    (@items [$handle:ident, $thread:ident, $msg:ident, $error:ty, $timeout:expr] [$($variants:tt)*] [$($methods:tt)*]
+        $vis:vis try_req fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty => $variant:ident; $($rest:tt)*
+    ) => {
+        actor!(@items [$handle, $thread, $msg, $error, $timeout] [$($variants)* $variant($crate::mpsc::Sender<::std::result::Result<$ret, $error>> $(, $ty)*),] [$($methods)*
+            $vis fn $name(&self $(, $arg: $ty)*) -> ::std::result::Result<$ret, $error> {
+                let (tx, rx) = $crate::mpsc::channel();
+                match $crate::request(&self.thread, $msg::$variant(tx $(, $arg)*), rx, $timeout, concat!(stringify!($msg), "::", stringify!($variant))) {
+                    Ok(reply) => reply,
+                    Err(e) => Err(e.into()),
+                }
+            }
+        ] $($rest)*);
+    };
+
+    (@items [$handle:ident, $thread:ident, $msg:ident, $error:ty, $timeout:expr] [$($variants:tt)*] [$($methods:tt)*]
+        $vis:vis call fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty => $variant:ident; $($rest:tt)*
+    ) => {
+        actor!(@items [$handle, $thread, $msg, $error, $timeout] [$($variants)* $variant($crate::mpsc::Sender<$ret> $(, $ty)*),] [$($methods)*
+            $vis fn $name(&self $(, $arg: $ty)*) -> $ret {
+                let (tx, rx) = $crate::mpsc::channel();
+                $crate::request(&self.thread, $msg::$variant(tx $(, $arg)*), rx, $timeout, concat!(stringify!($msg), "::", stringify!($variant)))
+                    .unwrap()
+            }
+        ] $($rest)*);
+    };
+
+    (@items [$handle:ident, $thread:ident, $msg:ident, $error:ty, $timeout:expr] [$($variants:tt)*] [$($methods:tt)*]) => {
+        pub type $thread = $crate::mpsc::Sender<$msg>;
+
+        #[derive(Debug)]
+        pub enum $msg {
+            $($variants)*
+        }
+
+        #[derive(Debug, Clone)]
+        pub struct $handle {
+            thread: $thread,
+        }
+
+        impl $handle {
+            pub fn new(thread: $thread) -> Self {
+                $handle{ thread: thread }
+            }
+
+            $($methods)*
+        }
+    };
+}
+
+// sends a message carrying the reply Sender, and waits for the reply
+pub fn request<M, T>(thread: &::mpsc::Sender<M>, msg: M, rx: Receiver<T>, timeout_ms: u64, name: &'static str) -> Result<T, ChanError> {
+    if thread.send(msg).is_err() {
+        return Err(ChanError::SendError(name));
+    }
+    rx.recv_timeout(Duration::from_millis(timeout_ms)).map_err(|e| match e {
+        RecvTimeoutError::Timeout => ChanError::Timeout(name),
+        RecvTimeoutError::Disconnected => ChanError::RecvError(name),
+    })
+}
+
+// the receiving end of an actor, which handles its messages one at a time
+pub trait Worker {
+    type Msg;
+
+    // returns true once the worker is done
+    fn handle_msg(&mut self, msg: Self::Msg) -> bool;
+
+    // what to do once handling a message has panicked
+    fn recovery(&self) -> Recovery;
+}
+
+// Runs a worker on a thread of its own, until it is done or every handle to it has gone. The worker
+// is made on that thread, so it may hold things that can't be sent between threads, and is made
+// again by the same closure when it's to be restarted after a panic.
+pub fn spawn_worker<W, F>(name: &str, rx: Receiver<W::Msg>, mut make: F) where W: Worker, W::Msg: Send + 'static, F: FnMut() -> W + Send + 'static {
+    thread::Builder::new().name(name.to_string()).spawn(move || {
+        let mut worker = match catch_unwind(AssertUnwindSafe(&mut make)) {
+            Ok(worker) => worker,
+            Err(e) => return stop(format!("{} couldn't start: {}", thread_name(), panic_message(&*e)), Stop::Fatal),
+        };
+        for msg in rx.iter() {
+            let e = match catch_unwind(AssertUnwindSafe(|| worker.handle_msg(msg))) {
+                Ok(true) => return,
+                Ok(false) => continue,
+                Err(e) => e,
+            };
+            let recovery = worker.recovery();
+            lprintln!("Worker panicked: {}, recovering with {:?}", panic_message(&*e), recovery);
+            match recovery {
+                Recovery::Restart => {
+                    worker = match catch_unwind(AssertUnwindSafe(&mut make)) {
+                        Ok(worker) => worker,
+                        Err(e) => return stop(format!("{} couldn't restart: {}", thread_name(), panic_message(&*e)), Stop::Fatal),
+                    };
+                },
+                Recovery::Resume => {},
+                Recovery::Shutdown => return stop(format!("{} panicked: {}", thread_name(), panic_message(&*e)), Stop::Fatal),
+            }
+        }
+        lprintln!("Worker stopped, every handle to it has gone");
+    }).unwrap();
+}
+
+fn thread_name() -> String {
+    thread::current().name().unwrap_or("unknown thread").to_string()
+}
+
+#[test]
+fn actor_test() {
+    use mpsc::channel;
+
+    #[derive(Debug)]
+    enum Error {
+        Chan(ChanError),
+        Odd,
+    }
+
+    impl From<ChanError> for Error {
+        fn from(err: ChanError) -> Error {
+            Error::Chan(err)
+        }
+    }
+
+    actor!{
+        pub struct Counter(CounterThread, CounterThreadMsg) -> Error, timeout_ms 100;
+        pub send fn add(n: u32) => Add;
+        pub req fn total() -> u32 => Total;
+        pub try_req fn half() -> u32 => Half;
+        pub call fn peek() -> u32 => Peek;
+        msg Hang;
+        msg Exit;
+    }
+
+    struct CounterWorker {
+        total: u32,
+    }
+
+    impl Worker for CounterWorker {
+        type Msg = CounterThreadMsg;
+
+        fn handle_msg(&mut self, msg: CounterThreadMsg) -> bool {
+            match msg {
+                CounterThreadMsg::Add(n) => self.total += n,
+                CounterThreadMsg::Total(s) | CounterThreadMsg::Peek(s) => { s.send(self.total).unwrap(); },
+                CounterThreadMsg::Half(s) => {
+                    s.send(match self.total % 2 {
+                        0 => Ok(self.total / 2),
+                        _ => Err(Error::Odd),
+                    }).unwrap();
+                },
+                CounterThreadMsg::Hang => thread::sleep(Duration::from_millis(300)),
+                CounterThreadMsg::Exit => return true,
+            }
+            false
+        }
+
+        fn recovery(&self) -> Recovery {
+            Recovery::Resume
+        }
+    }
+
+    let (tx, rx) = channel();
+    spawn_worker("CounterThread", rx, || CounterWorker{ total: 0 });
+    let counter = Counter::new(tx);
+    counter.add(3).unwrap();
+    assert_eq!(counter.total().unwrap(), 3);
+    assert_eq!(counter.peek(), 3);
+    match counter.half() {
+        Err(Error::Odd) => {},
+        other => panic!("expected the worker's own error, got {:?}", other),
+    }
+    counter.add(1).unwrap();
+    assert_eq!(counter.half().unwrap(), 2);
+
+    // a worker that doesn't answer in time
+    counter.thread.send(CounterThreadMsg::Hang).unwrap();
+    match counter.total() {
+        Err(Error::Chan(ChanError::Timeout(name))) => assert_eq!(name, "CounterThreadMsg::Total"),
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+
+    // nor once it has gone
+    counter.thread.send(CounterThreadMsg::Exit).unwrap();
+    thread::sleep(Duration::from_millis(400));
+    match counter.add(1) {
+        Err(Error::Chan(ChanError::SendError(name))) => assert_eq!(name, "CounterThreadMsg::Add"),
+        other => panic!("expected a send error, got {:?}", other),
+    }
+}
@@ -0,0 +1,37 @@
+use std::net::IpAddr;
+
+// entries are either a single address or a network in CIDR notation
+pub fn cidr_contains(entry: &str, addr: &IpAddr) -> bool {
+    let mut parts = entry.splitn(2, '/');
+    let base: IpAddr = match parts.next().unwrap_or("").parse() {
+        Ok(base) => base,
+        Err(_) => return false,
+    };
+    let bits = parts.next().and_then(|bits| bits.parse().ok());
+    match (base, addr) {
+        (IpAddr::V4(base), &IpAddr::V4(addr)) => {
+            let (mut base, mut addr) = (base.octets(), addr.octets());
+            let bits = bits.unwrap_or(32);
+            mask_octets(&mut base, bits);
+            mask_octets(&mut addr, bits);
+            base == addr
+        },
+        (IpAddr::V6(base), &IpAddr::V6(addr)) => {
+            let (mut base, mut addr) = (base.octets(), addr.octets());
+            let bits = bits.unwrap_or(128);
+            mask_octets(&mut base, bits);
+            mask_octets(&mut addr, bits);
+            base == addr
+        },
+        _ => false,
+    }
+}
+
+pub fn mask_octets(octets: &mut [u8], bits: u8) {
+    for (i, octet) in octets.iter_mut().enumerate() {
+        let keep = (bits as usize).saturating_sub(i * 8);
+        if keep < 8 {
+            *octet &= !(0xffu8 >> keep);
+        }
+    }
+}
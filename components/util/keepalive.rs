@@ -0,0 +1,153 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, PartialEq)]
+pub enum KeepaliveEvent {
+    Wait,
+    Ping(String), // Token
+    Timeout(u64), // Seconds since the connection was last heard from
+    Deadline,
+}
+
+// Tracks whether the other end of a connection is still there. Once it has been idle for
+// ping_freq it is sent a PING, and it times out unless the matching PONG arrives within
+// ping_timeout. A worker drives it from its event loop:
+//
+//     lselect_timeout!{ keepalive.next_timeout_ms() => match keepalive.poll() { ... }, ... }
+#[derive(Debug)]
+pub struct Keepalive {
+    ping_freq: Duration,
+    ping_timeout: Duration,
+    last_activity: Instant,
+    pending: Option<(String, Instant)>, // Token, Sent At
+    deadline: Option<Instant>,
+    seed: u64,
+    sent: u64,
+}
+
+impl Keepalive {
+    pub fn new(ping_freq: Duration, ping_timeout: Duration) -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos() as u64).unwrap_or(0);
+        Keepalive{
+            ping_freq: ping_freq,
+            ping_timeout: ping_timeout,
+            last_activity: Instant::now(),
+            pending: None,
+            deadline: None,
+            seed: seed,
+            sent: 0,
+        }
+    }
+
+    // a hard limit regardless of activity, eg for completing registration
+    pub fn set_deadline(&mut self, after: Duration) {
+        self.deadline = Some(Instant::now() + after);
+    }
+
+    pub fn clear_deadline(&mut self) {
+        self.deadline = None;
+    }
+
+    // anything read from the connection postpones the next PING
+    pub fn received(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    pub fn pong(&mut self, token: &str) {
+        let matches = match self.pending {
+            Some((ref pending, _)) => pending == token,
+            None => false,
+        };
+        if matches {
+            self.pending = None;
+        }
+    }
+
+    pub fn next_timeout_ms(&self) -> u32 {
+        let now = Instant::now();
+        let next = self.next_event();
+        if next <= now {
+            0
+        } else {
+            let wait = next - now;
+            // rounded up, waking early would only go back to sleep for a millisecond
+            (wait.as_secs() * 1000 + (wait.subsec_nanos() as u64).div_ceil(1000000)) as u32
+        }
+    }
+
+    pub fn poll(&mut self) -> KeepaliveEvent {
+        self.poll_at(Instant::now())
+    }
+
+    fn poll_at(&mut self, now: Instant) -> KeepaliveEvent {
+        if let Some(deadline) = self.deadline {
+            if now >= deadline {
+                return KeepaliveEvent::Deadline;
+            }
+        }
+        match self.pending {
+            Some((_, sent_at)) => {
+                if now >= sent_at + self.ping_timeout {
+                    return KeepaliveEvent::Timeout((now - self.last_activity).as_secs());
+                }
+                KeepaliveEvent::Wait
+            },
+            None => {
+                if now < self.last_activity + self.ping_freq {
+                    return KeepaliveEvent::Wait;
+                }
+                self.sent += 1;
+                let token = format!("{:X}", self.seed.wrapping_mul(31).wrapping_add(self.sent));
+                self.pending = Some((token.clone(), now));
+                KeepaliveEvent::Ping(token)
+            },
+        }
+    }
+
+    fn next_event(&self) -> Instant {
+        let next = match self.pending {
+            Some((_, sent_at)) => sent_at + self.ping_timeout,
+            None => self.last_activity + self.ping_freq,
+        };
+        match self.deadline {
+            Some(deadline) if deadline < next => deadline,
+            _ => next,
+        }
+    }
+}
+
+#[test]
+fn keepalive_test() {
+    let secs = Duration::from_secs;
+    let mut keepalive = Keepalive::new(secs(120), secs(60));
+    let start = keepalive.last_activity;
+    assert_eq!(keepalive.poll_at(start + secs(119)), KeepaliveEvent::Wait);
+    assert!(keepalive.next_timeout_ms() > 119000);
+
+    // idle for long enough to be pinged, answering in time keeps the connection
+    let token = match keepalive.poll_at(start + secs(120)) {
+        KeepaliveEvent::Ping(token) => token,
+        event => panic!("unexpected event {:?}", event),
+    };
+    assert_eq!(keepalive.poll_at(start + secs(150)), KeepaliveEvent::Wait);
+    keepalive.pong("not the token");
+    keepalive.pong(&token);
+    keepalive.last_activity = start + secs(150);
+    assert_eq!(keepalive.poll_at(start + secs(200)), KeepaliveEvent::Wait);
+
+    // activity alone doesn't answer a PING
+    let token = match keepalive.poll_at(start + secs(270)) {
+        KeepaliveEvent::Ping(next) => next,
+        event => panic!("unexpected event {:?}", event),
+    };
+    keepalive.last_activity = start + secs(280);
+    assert_eq!(keepalive.poll_at(start + secs(330)), KeepaliveEvent::Timeout(50));
+    keepalive.pong(&token);
+
+    let mut keepalive = Keepalive::new(secs(120), secs(60));
+    keepalive.set_deadline(secs(30));
+    assert!(keepalive.next_timeout_ms() <= 30000);
+    let start = keepalive.last_activity;
+    assert_eq!(keepalive.poll_at(start + secs(31)), KeepaliveEvent::Deadline);
+    keepalive.clear_deadline();
+    assert_eq!(keepalive.poll_at(start + secs(31)), KeepaliveEvent::Wait);
+}
@@ -1,9 +1,21 @@
-extern crate schedule_recv;
+extern crate crossbeam_channel;
 
+#[macro_use]
+pub mod lprintln;
 pub mod mpsc;
+#[macro_use]
+pub mod actor;
+pub mod supervisor;
 pub mod timer;
-pub mod lprintln;
+pub mod keepalive;
+pub mod cidr;
+pub mod types;
 
 pub use mpsc::*;
+pub use actor::*;
+pub use supervisor::*;
 pub use timer::*;
 pub use lprintln::*;
+pub use keepalive::*;
+pub use cidr::*;
+pub use types::*;
@@ -1,59 +1,38 @@
-pub use schedule_recv::oneshot_ms;
+// Actors talk over crossbeam channels, which unlike std's can be selected over on stable. They are
+// used like std::sync::mpsc, and are unbounded the same way.
+pub use crossbeam_channel::{Sender, Receiver, SendError, RecvError, TryRecvError, RecvTimeoutError, select, after};
 
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    ::crossbeam_channel::unbounded()
+}
+
+// runs $code if none of the receivers has anything within $time_ms
 #[macro_export]
 macro_rules! lselect_timeout {
-    ($time_ms:expr => $code:expr, $($args:tt)*) => ({
-        let timeout_rx = $crate::oneshot_ms($time_ms);
-        lselect!(
-            _ = timeout_rx => $code,
-            $($args)*
-        )
+    ($time_ms:expr => $code:expr, $($name:pat = $handle:expr => $c:expr),+ $(,)*) => ({
+        $crate::mpsc::select! {
+            $( recv($handle) -> $name => $c, )+
+            recv($crate::mpsc::after(::std::time::Duration::from_millis(($time_ms) as u64))) -> _ => $code,
+        }
     });
 }
 
+// blocks until one of the receivers has a message, or has been disconnected, and runs its arm
+// with the result of receiving from it
 #[macro_export]
 macro_rules! lselect {
-    (@internal
-        [$($parsed:tt)*]
-        $name:pat = $handle:expr => $code:expr, 
-        $($rest:tt)*
-    ) => ({
-        lselect!(@internal [$($parsed)* rx rx2 $name = $handle => $code,]
-                         $($rest)*)
-    });
-    
-    (@internal
-        [$($parsed:tt)*]
-        $name:pat = $handle:expr => $code:expr
-    ) => ({
-        lselect!([$($parsed)* rx rx2 $name = $handle => $code,])
-    });
-
-    (@internal
-        [$($rx:ident $output:ident $name:pat = $handle:expr => $code:expr,)*]
-    ) => ({
-        $( let mut $output = None; )+
-        {
-            use std::sync::mpsc::Select;
-            let sel = Select::new();
-            $( let mut $rx = sel.handle(&$handle); )+
-            unsafe {
-                $( $rx.add(); )+
-            }
-            let ret = sel.wait();
-            $( if ret == $rx.id() { $output = Some($rx.recv()); } )+ 
+    ($($name:pat = $handle:expr => $c:expr),+ $(,)*) => ({
+        $crate::mpsc::select! {
+            $( recv($handle) -> $name => $c, )+
         }
-        $( if let Some($name) = $output { $code } else )+
-        { unreachable!() }
     });
-    
-    ($($args:tt)*) => ( lselect!(@internal [] $($args)* ) );
 }
 
 #[derive(Debug)]
 pub enum ChanError {
     SendError(&'static str),
     RecvError(&'static str),
+    Timeout(&'static str), // No reply in time
 }
 
 #[macro_export]
@@ -70,21 +49,58 @@ macro_rules! send {
 #[macro_export]
 macro_rules! req_rep {
     ($sender:expr, $path:path => ( $($arg:expr),* )) => {{
-        let (tx, rx) = channel();
+        let (tx, rx) = $crate::mpsc::channel();
         let data = $path(tx, $($arg),*);
-        let ret = $sender.send(data)
+        $sender.send(data)
             .map_err(|_e| { // todo: do something intelligent with this arg
                 $crate::ChanError::SendError(stringify!($path))
-            });
-        let finalres: ::std::result::Result<_, $crate::ChanError>;
-        if ret.is_err() {
-            finalres = Err(ret.unwrap_err());
-        } else {
-            finalres = rx.recv()
-            .map_err(|_e| {
+            })
+            .and_then(|_| rx.recv().map_err(|_e| {
                 $crate::ChanError::RecvError(stringify!($path))
-            });
-        }
-        finalres
+            }))
     }}
 }
+
+#[test]
+fn select_test() {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[derive(Debug)]
+    enum Msg {
+        Echo(Sender<u32>, u32),
+    }
+
+    let (a_tx, a_rx) = channel::<u32>();
+    let (b_tx, b_rx) = channel::<u32>();
+    b_tx.send(2).unwrap();
+    let got = lselect!{
+        msg = a_rx => msg.map(|n| n + 100),
+        msg = b_rx => msg,
+    };
+    assert_eq!(got, Ok(2));
+
+    // nothing arrives, so the timeout arm runs
+    let start = Instant::now();
+    let timed_out = lselect_timeout!{50 => true,
+        _msg = a_rx => false,
+    };
+    assert!(timed_out && start.elapsed() >= Duration::from_millis(50));
+
+    // a receiver whose senders have gone is ready with an error
+    drop(a_tx);
+    let got = lselect_timeout!{1000 => None,
+        msg = a_rx => Some(msg.is_err()),
+    };
+    assert_eq!(got, Some(true));
+
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        for msg in rx.iter() {
+            match msg {
+                Msg::Echo(s, n) => s.send(n).unwrap(),
+            }
+        }
+    });
+    assert_eq!(req_rep!(tx, Msg::Echo => (7)).unwrap(), 7);
+}
@@ -0,0 +1,123 @@
+use std::any::Any;
+use std::process;
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// What becomes of a worker once handling a message has panicked. The message is lost either way,
+// and whoever was waiting on a reply to it gets a RecvError.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    Restart,  // a fresh worker takes over, for workers whose state can be made again
+    Resume,   // the same worker carries on, for state that a half handled message leaves usable
+    Shutdown, // the server stops, for state that can neither be trusted nor made again
+}
+
+// why the server is stopping, which decides what the process does once it has
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Die,     // asked to, by a signal or DIE
+    Restart, // asked to come back up, by RESTART
+    Fatal,   // a worker that the server can't do without has failed
+}
+
+type StopHandler = Box<dyn Fn(String, Stop) + Send>;
+
+static STOP: Mutex<Option<StopHandler>> = Mutex::new(None);
+
+// replaces what stopping the server does, which is exiting the process unless one is set
+pub fn set_stop_handler<F>(handler: F) where F: Fn(String, Stop) + Send + 'static {
+    *STOP.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(handler));
+}
+
+pub fn stop(reason: String, how: Stop) {
+    lprintln!("Stopping ({:?}): {}", how, reason);
+    match *STOP.lock().unwrap_or_else(|e| e.into_inner()) {
+        Some(ref handler) => handler(reason, how),
+        None => process::exit(match how {
+            Stop::Fatal => 1,
+            _ => 0,
+        }),
+    }
+}
+
+// joins a thread unless it's still running at the deadline, returns whether it ended in time
+pub fn join_until(thread: JoinHandle<()>, deadline: Instant) -> bool {
+    while !thread.is_finished() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    thread.join().is_ok()
+}
+
+// the text a panic was raised with, which is nearly always a &str or a String
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(msg) => msg.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(msg) => msg.clone(),
+            None => "unknown panic".into(),
+        },
+    }
+}
+
+#[test]
+fn supervisor_test() {
+    use mpsc::channel;
+    use {ChanError, Worker, spawn_worker};
+
+    actor!{
+        pub struct Counter(CounterThread, CounterThreadMsg) -> ChanError, timeout_ms 500;
+        pub send fn add(n: u32) => Add;
+        pub req fn total() -> u32 => Total;
+        pub send fn boom() => Boom;
+    }
+
+    struct CounterWorker {
+        total: u32,
+        recovery: Recovery,
+    }
+
+    impl Worker for CounterWorker {
+        type Msg = CounterThreadMsg;
+
+        fn handle_msg(&mut self, msg: CounterThreadMsg) -> bool {
+            match msg {
+                CounterThreadMsg::Add(n) => self.total += n,
+                CounterThreadMsg::Total(s) => { s.send(self.total).unwrap(); },
+                CounterThreadMsg::Boom => panic!("injected"),
+            }
+            false
+        }
+
+        fn recovery(&self) -> Recovery {
+            self.recovery
+        }
+    }
+
+    let spawn = |recovery| {
+        let (tx, rx) = channel();
+        spawn_worker("CounterThread", rx, move || CounterWorker{ total: 0, recovery: recovery });
+        let counter = Counter::new(tx);
+        counter.add(2).unwrap();
+        counter.boom().unwrap();
+        counter
+    };
+
+    // a restarted worker starts over, a resumed one keeps what it had
+    assert_eq!(spawn(Recovery::Restart).total().unwrap(), 0);
+    assert_eq!(spawn(Recovery::Resume).total().unwrap(), 2);
+
+    // and one that can't carry on asks for the server to stop, after which it's gone
+    let (tx, rx) = channel();
+    set_stop_handler(move |reason, how| { tx.send((reason, how)).unwrap(); });
+    let counter = spawn(Recovery::Shutdown);
+    assert_eq!(rx.recv_timeout(Duration::from_millis(500)).unwrap(), ("CounterThread panicked: injected".to_string(), Stop::Fatal));
+    thread::sleep(Duration::from_millis(100));
+    match counter.total() {
+        Err(ChanError::SendError(_)) => {},
+        other => panic!("expected the worker to have gone, got {:?}", other),
+    }
+}
@@ -1,4 +1,4 @@
-use std::sync::mpsc::{channel,Sender,Receiver};
+use mpsc::{channel,Sender,Receiver};
 use std::time::Duration;
 use std::thread;
 
@@ -51,7 +51,7 @@ pub struct TimerManager {
 
 #[test]
 fn timer_test() {
-    let (timer, controller) = Timer::new();
+    let (_timer, _controller) = Timer::new();
 }
 
 impl TimerManager {
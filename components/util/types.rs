@@ -0,0 +1,203 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const NICKLEN: usize = 30;
+pub const CHANNELLEN: usize = 50;
+
+// Nicks and channel names compare and hash the way CASEMAPPING=ascii says they do, so "Alice" and
+// "alice" are the same key everywhere, while the case they were given in is kept for display.
+macro_rules! casemapped {
+    ($name:ident) => {
+        #[derive(Debug, Clone)]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl PartialEq for $name {
+            fn eq(&self, other: &$name) -> bool {
+                self.0.eq_ignore_ascii_case(&other.0)
+            }
+        }
+
+        impl Eq for $name {}
+
+        impl Hash for $name {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                for byte in self.0.bytes() {
+                    state.write_u8(byte.to_ascii_lowercase());
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+// free text that ends up in the middle of a line, anything that would end the line early is dropped
+macro_rules! text {
+    ($name:ident) => {
+        #[derive(Debug, Clone, PartialEq, Eq, Default)]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(text: String) -> Self {
+                match text.contains(&['\r', '\n', '\0'][..]) {
+                    true => $name(text.chars().filter(|&c| c != '\r' && c != '\n' && c != '\0').collect()),
+                    false => $name(text),
+                }
+            }
+        }
+
+        impl<'a> From<&'a str> for $name {
+            fn from(text: &'a str) -> Self {
+                $name::from(text.to_string())
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+casemapped!(Nick);
+casemapped!(ChannelName);
+text!(Reason);
+text!(MessageText);
+
+fn is_special(c: char) -> bool {
+    "[]\\`_^{|}".contains(c)
+}
+
+impl Nick {
+    // a letter or one of []\`_^{|} first, then letters, digits, those and -
+    pub fn parse(nick: &str) -> Option<Nick> {
+        let mut chars = nick.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || is_special(c) => {},
+            _ => return None,
+        }
+        if nick.len() > NICKLEN || !chars.all(|c| c.is_ascii_alphanumeric() || is_special(c) || c == '-') {
+            return None;
+        }
+        Some(Nick(nick.into()))
+    }
+}
+
+impl ChannelName {
+    pub fn parse(name: &str) -> Option<ChannelName> {
+        if !name.starts_with('#') || name.len() < 2 || name.len() > CHANNELLEN {
+            return None;
+        }
+        if name.contains(&[' ', ',', '\x07', '\r', '\n', '\0'][..]) {
+            return None;
+        }
+        Some(ChannelName(name.into()))
+    }
+}
+
+// The source of a message, nick!user@host for users. Those relayed by other servers may only carry
+// a nick or a server name, so anything that fits in a prefix is accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hostmask(String);
+
+impl Hostmask {
+    pub fn new(nick: &Nick, user: &str, host: &str) -> Self {
+        Hostmask(format!("{}!{}@{}", nick, user, host))
+    }
+
+    pub fn parse(prefix: &str) -> Option<Hostmask> {
+        if prefix.is_empty() || prefix.starts_with(':') || prefix.contains(|c: char| c.is_whitespace() || c == '\0') {
+            return None;
+        }
+        Some(Hostmask(prefix.into()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Hostmask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// seconds since the epoch, as used for nick and channel creation times between servers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    pub fn now() -> Self {
+        Timestamp(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0))
+    }
+
+    pub fn parse(timestamp: &str) -> Option<Timestamp> {
+        timestamp.parse().ok().map(Timestamp)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[test]
+fn types_test() {
+    use std::collections::HashMap;
+
+    assert!(Nick::parse("alice").is_some());
+    assert!(Nick::parse("[a]-b`_").is_some());
+    assert!(Nick::parse("").is_none());
+    assert!(Nick::parse("1alice").is_none());
+    assert!(Nick::parse("-alice").is_none());
+    assert!(Nick::parse("al ice").is_none());
+    assert!(Nick::parse("alice!").is_none());
+    assert!(Nick::parse(&"a".repeat(NICKLEN + 1)).is_none());
+
+    // nicks are told apart regardless of case, but keep the case they were given in
+    let nick = Nick::parse("Alice").unwrap();
+    assert_eq!(nick, Nick::parse("aLICE").unwrap());
+    assert_eq!(nick.to_string(), "Alice");
+    let mut nicks = HashMap::new();
+    nicks.insert(nick, 1);
+    assert_eq!(nicks.get(&Nick::parse("alice").unwrap()), Some(&1));
+
+    assert!(ChannelName::parse("#rust").is_some());
+    assert!(ChannelName::parse("rust").is_none());
+    assert!(ChannelName::parse("#").is_none());
+    assert!(ChannelName::parse("#a,#b").is_none());
+    assert!(ChannelName::parse("#a b").is_none());
+    assert_eq!(ChannelName::parse("#Rust"), ChannelName::parse("#rUST"));
+
+    assert_eq!(Hostmask::new(&Nick::parse("alice").unwrap(), "~a", "host").as_str(), "alice!~a@host");
+    assert!(Hostmask::parse("irc.example.org").is_some());
+    assert!(Hostmask::parse("a b").is_none());
+    assert!(Hostmask::parse(":a").is_none());
+
+    // text can't smuggle in a line of its own
+    assert_eq!(MessageText::from("hi\r\nQUIT :bye").as_str(), "hiQUIT :bye");
+    assert_eq!(Reason::from("fine").as_str(), "fine");
+
+    assert_eq!(Timestamp::parse("123456"), Some(Timestamp(123456)));
+    assert_eq!(Timestamp::parse("soon"), None);
+}